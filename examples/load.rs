@@ -10,8 +10,7 @@ extern crate serde;
 extern crate serde_derive;
 
 use collectd_plugin::{
-    ConfigItem, Plugin, PluginCapabilities, PluginManager, PluginRegistration, Value,
-    ValueListBuilder,
+    Plugin, PluginCapabilities, PluginRegistration, TypedPluginManager, Value, ValueListBuilder,
 };
 use failure::Error;
 
@@ -38,16 +37,16 @@ struct AbsoluteLoadPlugin;
 /// demonstrate that that's not necessary, we create a separate unit struct.
 struct LoadManager;
 
-impl PluginManager for LoadManager {
+impl TypedPluginManager for LoadManager {
+    // The collectd configuration is deserialized into our configuration struct, or the default
+    // configuration is used when the plugin is not configured
+    type Config = LoadConfig;
+
     fn name() -> &'static str {
         "loadrust"
     }
 
-    fn plugins(config: Option<&[ConfigItem]>) -> Result<PluginRegistration, Error> {
-        // Deserialize the collectd configuration into our configuration struct
-        let config: LoadConfig =
-            collectd_plugin::de::from_collectd(config.unwrap_or_else(Default::default))?;
-
+    fn plugins_typed(config: LoadConfig) -> Result<PluginRegistration, Error> {
         // Grab the configuration. By default, this plugin reports absolute load values. For
         // demonstration purposes, there are two different plugin types (relative and absolute),
         // but one could easily fold the `num_cpus` field as an optional (or use `1` into a single
@@ -86,7 +85,7 @@ impl Plugin for AbsoluteLoadPlugin {
         PluginCapabilities::READ
     }
 
-    fn read_values(&mut self) -> Result<(), Error> {
+    fn read_values(&self) -> Result<(), Error> {
         // Create a list of values to submit to collectd. We'll be sending in a vector representing the
         // "load" type. Short-term load is first followed by mid-term and long-term. The number of
         // values that you submit at a time depends on types.db in collectd configurations
@@ -104,7 +103,7 @@ impl Plugin for RelativeLoadPlugin {
         PluginCapabilities::READ
     }
 
-    fn read_values(&mut self) -> Result<(), Error> {
+    fn read_values(&self) -> Result<(), Error> {
         // Essentially the same as `AbsoluteLoadPlugin`, but divides each load value by the number
         // of cpus and submits the values as the type of "relative"
         let values: Vec<Value> = get_load()?
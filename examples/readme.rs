@@ -33,7 +33,7 @@ impl Plugin for MyPlugin {
         PluginCapabilities::READ
     }
 
-    fn read_values(&mut self) -> Result<(), Error> {
+    fn read_values(&self) -> Result<(), Error> {
         // Create a list of values to submit to collectd. We'll be sending in a vector representing the
         // "load" type. Short-term load is first (15.0) followed by mid-term and long-term. The number
         // of values that you submit at a time depends on types.db in collectd configurations
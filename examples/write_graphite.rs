@@ -8,8 +8,8 @@ extern crate serde;
 extern crate serde_derive;
 
 use collectd_plugin::{
-    collectd_log, ConfigItem, LogLevel, Plugin, PluginCapabilities, PluginManager,
-    PluginRegistration, Value, ValueList,
+    collectd_log, LogLevel, Plugin, PluginCapabilities, PluginRegistration, TypedPluginManager,
+    Value, ValueList,
 };
 use failure::Error;
 use std::borrow::Cow;
@@ -58,16 +58,14 @@ struct GraphitePlugin<W: Write + Send> {
 
 struct GraphiteManager;
 
-impl PluginManager for GraphiteManager {
+impl TypedPluginManager for GraphiteManager {
+    type Config = GraphiteConfig;
+
     fn name() -> &'static str {
         "write_graphite_rust"
     }
 
-    fn plugins(config: Option<&[ConfigItem]>) -> Result<PluginRegistration, Error> {
-        // Deserialize the collectd configuration into our configuration struct
-        let config: GraphiteConfig =
-            collectd_plugin::de::from_collectd(config.unwrap_or_else(Default::default))?;
-
+    fn plugins_typed(config: GraphiteConfig) -> Result<PluginRegistration, Error> {
         let config: Result<Vec<(String, Box<Plugin>)>, Error> = config
             .nodes
             .into_iter()
@@ -108,7 +106,7 @@ fn graphitize(s: &str) -> Cow<str> {
 }
 
 impl<W: Write + Send> GraphitePlugin<W> {
-    fn write_value(&mut self, mut line: String, val: Value, dt: &str) {
+    fn write_value(&self, mut line: String, val: Value, dt: &str) {
         line.push(' ');
         line.push_str(val.to_string().as_str());
         line.push(' ');
@@ -130,7 +128,7 @@ impl<W: Write + Send> Plugin for GraphitePlugin<W> {
         PluginCapabilities::WRITE
     }
 
-    fn write_values<'a>(&mut self, list: ValueList<'a>) -> Result<(), Error> {
+    fn write_values<'a>(&self, list: ValueList<'a>) -> Result<(), Error> {
         // We use a heap allocated string to construct data to send to graphite. Collectd doesn't
         // use the heap (preferring fixed size arrays). We could get the same behavior using the
         // ArrayString type from the arrayvec crate.
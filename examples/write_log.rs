@@ -30,7 +30,7 @@ impl Plugin for TestWritePlugin {
         PluginCapabilities::WRITE
     }
 
-    fn write_values<'a>(&mut self, list: ValueList<'a>) -> Result<(), Error> {
+    fn write_values<'a>(&self, list: ValueList<'a>) -> Result<(), Error> {
         let values = list
             .values
             .iter()
@@ -0,0 +1,234 @@
+use super::{from_array, length, to_array_res, Value, ValueType};
+use bindings::{
+    data_set_t, data_source_t, plugin_get_ds, plugin_register_data_set, value_list_t,
+    DS_TYPE_ABSOLUTE, DS_TYPE_COUNTER, DS_TYPE_DERIVE, DS_TYPE_GAUGE,
+};
+use errors::{RegistrationError, SubmitError};
+use failure::{Error, ResultExt};
+use std::f64;
+use std::os::raw::c_int;
+use std::slice;
+
+/// A single data source (eg: `rx` or `tx` of the `if_octets` type) within a data set
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DataSource<'a> {
+    /// Name of the data source. Data sets with a single data source conventionally name it "value"
+    pub name: &'a str,
+
+    /// How collectd should interpret values submitted for the data source
+    pub kind: ValueType,
+
+    /// Minimum value for the data source. `None` means the minimum is unknown (`U` in types.db)
+    pub min: Option<f64>,
+
+    /// Maximum value for the data source. `None` means the maximum is unknown (`U` in types.db)
+    pub max: Option<f64>,
+}
+
+impl<'a> DataSource<'a> {
+    /// Creates a data source without a minimum or maximum
+    pub fn new(name: &'a str, kind: ValueType) -> DataSource<'a> {
+        DataSource {
+            name,
+            kind,
+            min: None,
+            max: None,
+        }
+    }
+
+    /// Sets the minimum value of the data source
+    pub fn min(mut self, min: f64) -> DataSource<'a> {
+        self.min = Some(min);
+        self
+    }
+
+    /// Sets the maximum value of the data source
+    pub fn max(mut self, max: f64) -> DataSource<'a> {
+        self.max = Some(max);
+        self
+    }
+}
+
+/// The definition of a type (what would otherwise be a line in types.db), which declares the
+/// data sources of the values that are submitted under the type.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DataSet<'a> {
+    /// Name of the type (eg: `if_octets`)
+    pub type_: &'a str,
+
+    /// The data sources in the order that values are submitted
+    pub sources: Vec<DataSource<'a>>,
+}
+
+impl<'a> DataSet<'a> {
+    pub fn new(type_: &'a str, sources: Vec<DataSource<'a>>) -> DataSet<'a> {
+        DataSet { type_, sources }
+    }
+
+    /// Registers the data set with collectd, so that values can be submitted under its type. A
+    /// data set that is already known to collectd (eg: from types.db) is replaced.
+    pub fn register(&self) -> Result<(), Error> {
+        let mut sources = self
+            .sources
+            .iter()
+            .map(to_data_source)
+            .collect::<Result<Vec<data_source_t>, Error>>()?;
+
+        let set = to_data_set(self.type_, &mut sources)?;
+        match unsafe { plugin_register_data_set(&set) } {
+            0 => Ok(()),
+            i => Err(RegistrationError::DataSetError(i).into()),
+        }
+    }
+}
+
+/// Checks values against the data sources of the list's type, as collectd does when the values
+/// are dispatched, so that a mismatch is reported to the plugin that submitted the values instead
+/// of only being logged by collectd. Each of the given groups is checked as the values of one
+/// dispatch of the list, against a data set that is looked up once. The type is looked up by the
+/// NUL-terminated name that is already in the list, so nothing is allocated unless there is a
+/// mismatch.
+pub fn check_values<I>(list: &value_list_t, values: I) -> Result<(), Error>
+where
+    I: IntoIterator,
+    I::Item: AsRef<[Value]>,
+{
+    let set = unsafe { plugin_get_ds(list.type_.as_ptr()) };
+    if set.is_null() {
+        let (plugin, type_) = list_names(list)?;
+        return Err(SubmitError::UnknownTypeError { plugin, type_ }.into());
+    }
+
+    let set = unsafe { &*set };
+    let sources = unsafe { slice::from_raw_parts(set.ds, length(set.ds_num)) };
+    for values in values {
+        let values = values.as_ref();
+        if sources.len() != values.len() {
+            let (plugin, type_) = list_names(list)?;
+            return Err(SubmitError::ValueCountError {
+                plugin,
+                type_,
+                expected: sources.len(),
+                actual: values.len(),
+            }
+            .into());
+        }
+
+        for (source, value) in sources.iter().zip(values) {
+            match to_value_type(source.type_) {
+                Some(expected) if expected != value.value_type() => {
+                    let (plugin, type_) = list_names(list)?;
+                    let name = from_array(&source.name).context("data source name")?;
+                    return Err(SubmitError::ValueTypeError {
+                        plugin,
+                        type_,
+                        name: name.to_string(),
+                        expected,
+                        actual: value.value_type(),
+                    }
+                    .into());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// The plugin and type of the list, for reporting a mismatch
+fn list_names(list: &value_list_t) -> Result<(String, String), Error> {
+    let plugin = from_array(&list.plugin).context("plugin")?;
+    let type_ = from_array(&list.type_).context("type")?;
+    Ok((plugin.to_string(), type_.to_string()))
+}
+
+fn to_value_type(kind: c_int) -> Option<ValueType> {
+    match kind as u32 {
+        DS_TYPE_COUNTER => Some(ValueType::Counter),
+        DS_TYPE_GAUGE => Some(ValueType::Gauge),
+        DS_TYPE_DERIVE => Some(ValueType::Derive),
+        DS_TYPE_ABSOLUTE => Some(ValueType::Absolute),
+        _ => None,
+    }
+}
+
+fn to_data_source(source: &DataSource) -> Result<data_source_t, Error> {
+    let name = to_array_res(source.name)
+        .with_context(|_e| format!("data source name: {}", source.name))?;
+
+    // Collectd represents an unknown bound as NaN
+    Ok(data_source_t {
+        name,
+        type_: source.kind as i32,
+        min: source.min.unwrap_or(f64::NAN),
+        max: source.max.unwrap_or(f64::NAN),
+    })
+}
+
+fn to_data_set(type_: &str, sources: &mut [data_source_t]) -> Result<data_set_t, Error> {
+    let name = to_array_res(type_).with_context(|_e| format!("data set type: {}", type_))?;
+
+    #[cfg(collectd57)]
+    let len = sources.len();
+
+    #[cfg(not(collectd57))]
+    let len = sources.len() as i32;
+
+    // Collectd copies the data sources when registering, so the set only has to outlive the call
+    Ok(data_set_t {
+        type_: name,
+        ds_num: len,
+        ds: sources.as_mut_ptr(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{from_array, length};
+    use bindings::{DS_TYPE_DERIVE, DS_TYPE_GAUGE};
+
+    #[test]
+    fn test_to_data_set() {
+        let set = DataSet::new(
+            "my_octets",
+            vec![
+                DataSource::new("rx", ValueType::Derive).min(0.0),
+                DataSource::new("tx", ValueType::Gauge).min(0.0).max(100.0),
+            ],
+        );
+
+        let mut sources: Vec<data_source_t> = set
+            .sources
+            .iter()
+            .map(|x| to_data_source(x).unwrap())
+            .collect();
+        let conv = to_data_set(set.type_, &mut sources).unwrap();
+
+        assert_eq!(from_array(&conv.type_).unwrap(), "my_octets");
+        assert_eq!(length(conv.ds_num), 2);
+
+        assert_eq!(from_array(&sources[0].name).unwrap(), "rx");
+        assert_eq!(sources[0].type_, DS_TYPE_DERIVE as i32);
+        assert_eq!(sources[0].min, 0.0);
+        assert!(sources[0].max.is_nan());
+
+        assert_eq!(from_array(&sources[1].name).unwrap(), "tx");
+        assert_eq!(sources[1].type_, DS_TYPE_GAUGE as i32);
+        assert_eq!(sources[1].max, 100.0);
+    }
+
+    #[test]
+    fn test_register_data_set() {
+        let set = DataSet::new("my_gauge", vec![DataSource::new("value", ValueType::Gauge)]);
+        assert!(set.register().is_ok());
+
+        let long_name = "a".repeat(200);
+        let set = DataSet::new(
+            "my_gauge",
+            vec![DataSource::new(&long_name, ValueType::Gauge)],
+        );
+        assert!(set.register().is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use super::{empty_to_none, ValueList};
+use failure::{Error, ResultExt};
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw::c_char;
+
+/// Identifies a series of values, as given to `Plugin::flush`. Collectd writes identifiers as
+/// `host/plugin-plugin_instance/type-type_instance`, where the instances are optional.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Identifier<'a> {
+    /// The hostname where the values were collected
+    pub host: &'a str,
+
+    /// The plugin that submitted the values
+    pub plugin: &'a str,
+
+    /// Distinguishes entities of the same plugin (eg: each core for the cpu plugin)
+    pub plugin_instance: Option<&'a str>,
+
+    /// The type (from types.db) of the values
+    pub type_: &'a str,
+
+    /// Distinguishes values of the same type (eg: "free" and "used" memory)
+    pub type_instance: Option<&'a str>,
+}
+
+impl<'a> Identifier<'a> {
+    /// Parses an identifier with the same grammar as collectd: the host, plugin, and type are
+    /// separated by slashes, and an instance follows the first dash of the plugin or type.
+    pub fn parse(s: &'a str) -> Result<Identifier<'a>, Error> {
+        let mut parts = s.splitn(3, '/');
+        let host = parts.next().unwrap_or("");
+        let (plugin, type_) = match (parts.next(), parts.next()) {
+            (Some(plugin), Some(type_)) => (plugin, type_),
+            _ => return Err(format_err!("Identifier is missing a plugin or type: {}", s)),
+        };
+
+        let (plugin, plugin_instance) = split_instance(plugin);
+        let (type_, type_instance) = split_instance(type_);
+        Ok(Identifier {
+            host,
+            plugin,
+            plugin_instance,
+            type_,
+            type_instance,
+        })
+    }
+
+    /// Returns if the values in the list belong to the series of this identifier
+    pub fn matches(&self, list: &ValueList) -> bool {
+        self.host == list.host
+            && self.plugin == list.plugin
+            && self.plugin_instance == list.plugin_instance
+            && self.type_ == list.type_
+            && self.type_instance == list.type_instance
+    }
+}
+
+impl<'a> fmt::Display for Identifier<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.host, self.plugin)?;
+        if let Some(instance) = self.plugin_instance {
+            write!(f, "-{}", instance)?;
+        }
+
+        write!(f, "/{}", self.type_)?;
+        if let Some(instance) = self.type_instance {
+            write!(f, "-{}", instance)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Converts the identifier that collectd passes to a flush callback. Collectd passes NULL (or an
+/// empty string) when all values should be flushed.
+#[doc(hidden)]
+pub unsafe fn flush_identifier<'a>(
+    identifier: *const c_char,
+) -> Result<Option<Identifier<'a>>, Error> {
+    if identifier.is_null() {
+        return Ok(None);
+    }
+
+    let s = CStr::from_ptr(identifier)
+        .to_str()
+        .context("Flush identifier is not UTF-8")?;
+    match empty_to_none(s) {
+        Some(s) => Identifier::parse(s).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn split_instance(s: &str) -> (&str, Option<&str>) {
+    match s.find('-') {
+        Some(i) => (&s[..i], empty_to_none(&s[i + 1..])),
+        None => (s, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::MetaData;
+    use chrono::prelude::*;
+    use chrono::Duration;
+    use std::ffi::CString;
+    use std::ptr;
+
+    #[test]
+    fn test_identifier_parse() {
+        let id = Identifier::parse("localhost/cpu-0/cpu-idle").unwrap();
+        assert_eq!(id.host, "localhost");
+        assert_eq!(id.plugin, "cpu");
+        assert_eq!(id.plugin_instance, Some("0"));
+        assert_eq!(id.type_, "cpu");
+        assert_eq!(id.type_instance, Some("idle"));
+        assert_eq!(id.to_string(), "localhost/cpu-0/cpu-idle");
+
+        let id = Identifier::parse("localhost/load/load").unwrap();
+        assert_eq!(id.plugin_instance, None);
+        assert_eq!(id.type_instance, None);
+        assert_eq!(id.to_string(), "localhost/load/load");
+
+        let id = Identifier::parse("localhost/df-var-log/df_complex-free").unwrap();
+        assert_eq!(id.plugin_instance, Some("var-log"));
+        assert_eq!(id.type_instance, Some("free"));
+
+        assert!(Identifier::parse("localhost/load").is_err());
+        assert!(Identifier::parse("localhost").is_err());
+    }
+
+    #[test]
+    fn test_flush_identifier() {
+        assert_eq!(unsafe { flush_identifier(ptr::null()) }.unwrap(), None);
+
+        let empty = CString::new("").unwrap();
+        assert_eq!(unsafe { flush_identifier(empty.as_ptr()) }.unwrap(), None);
+
+        let s = CString::new("localhost/load/load").unwrap();
+        let id = unsafe { flush_identifier(s.as_ptr()) }.unwrap().unwrap();
+        assert_eq!(id.plugin, "load");
+
+        let s = CString::new("load").unwrap();
+        assert!(unsafe { flush_identifier(s.as_ptr()) }.is_err());
+    }
+
+    #[test]
+    fn test_identifier_matches() {
+        let list = ValueList {
+            values: vec![],
+            plugin: "cpu",
+            plugin_instance: Some("0"),
+            type_: "cpu",
+            type_instance: Some("idle"),
+            host: "localhost",
+            time: Utc::now(),
+            interval: Duration::seconds(10),
+            meta: MetaData::default(),
+        };
+
+        assert!(Identifier::parse("localhost/cpu-0/cpu-idle")
+            .unwrap()
+            .matches(&list));
+        assert!(!Identifier::parse("localhost/cpu-1/cpu-idle")
+            .unwrap()
+            .matches(&list));
+        assert!(!Identifier::parse("localhost/cpu/cpu-idle")
+            .unwrap()
+            .matches(&list));
+    }
+}
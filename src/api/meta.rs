@@ -0,0 +1,618 @@
+use bindings::{
+    meta_data_add_boolean, meta_data_add_double, meta_data_add_signed_int, meta_data_add_string,
+    meta_data_add_unsigned_int, meta_data_create, meta_data_destroy, meta_data_exists,
+    meta_data_get_boolean, meta_data_get_double, meta_data_get_signed_int, meta_data_get_string,
+    meta_data_get_unsigned_int, meta_data_t, meta_data_toc, meta_data_type, MD_TYPE_BOOLEAN,
+    MD_TYPE_DOUBLE, MD_TYPE_SIGNED_INT, MD_TYPE_STRING, MD_TYPE_UNSIGNED_INT,
+};
+use errors::SubmitError;
+use failure::{Error, ResultExt};
+use std::ffi::{CStr, CString};
+use std::marker::PhantomData;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::slice;
+use std::vec::IntoIter;
+
+extern "C" {
+    fn free(ptr: *mut c_void);
+}
+
+/// A typed value that is attached as meta data to a notification or value list
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MetaValue<'a> {
+    String(&'a str),
+    SignedInt(i64),
+    UnsignedInt(u64),
+    Double(f64),
+    Boolean(bool),
+}
+
+/// An owned version of `MetaValue`, as meta data values are copied out of collectd
+#[derive(Debug, PartialEq, Clone)]
+pub enum OwnedMetaValue {
+    String(String),
+    SignedInt(i64),
+    UnsignedInt(u64),
+    Double(f64),
+    Boolean(bool),
+}
+
+impl OwnedMetaValue {
+    /// The `MD_TYPE_*` constant that collectd uses for the value's type
+    fn md_type(&self) -> u32 {
+        match *self {
+            OwnedMetaValue::String(_) => MD_TYPE_STRING,
+            OwnedMetaValue::SignedInt(_) => MD_TYPE_SIGNED_INT,
+            OwnedMetaValue::UnsignedInt(_) => MD_TYPE_UNSIGNED_INT,
+            OwnedMetaValue::Double(_) => MD_TYPE_DOUBLE,
+            OwnedMetaValue::Boolean(_) => MD_TYPE_BOOLEAN,
+        }
+    }
+
+    /// Borrows the value, so that it can be attached to values or notifications that are submitted
+    pub fn as_meta_value<'a>(&'a self) -> MetaValue<'a> {
+        match *self {
+            OwnedMetaValue::String(ref x) => MetaValue::String(x),
+            OwnedMetaValue::SignedInt(x) => MetaValue::SignedInt(x),
+            OwnedMetaValue::UnsignedInt(x) => MetaValue::UnsignedInt(x),
+            OwnedMetaValue::Double(x) => MetaValue::Double(x),
+            OwnedMetaValue::Boolean(x) => MetaValue::Boolean(x),
+        }
+    }
+}
+
+impl<'a> From<MetaValue<'a>> for OwnedMetaValue {
+    fn from(value: MetaValue<'a>) -> OwnedMetaValue {
+        match value {
+            MetaValue::String(x) => OwnedMetaValue::String(x.to_string()),
+            MetaValue::SignedInt(x) => OwnedMetaValue::SignedInt(x),
+            MetaValue::UnsignedInt(x) => OwnedMetaValue::UnsignedInt(x),
+            MetaValue::Double(x) => OwnedMetaValue::Double(x),
+            MetaValue::Boolean(x) => OwnedMetaValue::Boolean(x),
+        }
+    }
+}
+
+/// The meta data attached to a value list that collectd passed to a plugin, which may have been
+/// set by the plugin that submitted the values or by a target of the filter chain. Nothing is
+/// decoded until an entry is accessed, and values are copied out of collectd on access. The view
+/// may instead be of entries that have been copied out already (eg: from an `OwnedValueList`).
+/// As in collectd, keys are matched case-insensitively.
+#[derive(Debug, Clone, Copy)]
+pub struct MetaData<'a> {
+    md: *mut meta_data_t,
+    marker: PhantomData<&'a meta_data_t>,
+    owned: &'a [(String, OwnedMetaValue)],
+}
+
+// Collectd guards each meta data with its own lock, and the view only ever reads from it, so the
+// view can be shared with (or sent to) other threads for as long as collectd keeps the meta data
+unsafe impl<'a> Send for MetaData<'a> {}
+unsafe impl<'a> Sync for MetaData<'a> {}
+
+impl<'a> PartialEq for MetaData<'a> {
+    /// Views are equal when they have the same entries, wherever the entries are stored. A view is
+    /// always equal to itself, but a view whose entries can't be decoded isn't equal to any other.
+    fn eq(&self, other: &MetaData<'a>) -> bool {
+        if self.md == other.md && ptr::eq(self.owned, other.owned) {
+            return true;
+        }
+
+        match (self.entries(), other.entries()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl<'a> Default for MetaData<'a> {
+    /// Meta data without any entries
+    fn default() -> MetaData<'a> {
+        MetaData {
+            md: ptr::null_mut(),
+            marker: PhantomData,
+            owned: &[],
+        }
+    }
+}
+
+impl<'a> MetaData<'a> {
+    /// Wraps collectd's meta data, which may be null
+    #[doc(hidden)]
+    pub fn from_ptr(md: *mut meta_data_t) -> MetaData<'a> {
+        MetaData {
+            md,
+            marker: PhantomData,
+            owned: &[],
+        }
+    }
+
+    /// A view of entries that have already been copied out of collectd
+    pub fn from_entries(entries: &'a [(String, OwnedMetaValue)]) -> MetaData<'a> {
+        MetaData {
+            md: ptr::null_mut(),
+            marker: PhantomData,
+            owned: entries,
+        }
+    }
+
+    /// Returns the keys of all entries
+    pub fn keys(&self) -> Result<Vec<String>, Error> {
+        if self.md.is_null() {
+            return Ok(self.owned.iter().map(|x| x.0.clone()).collect());
+        }
+
+        let mut toc: *mut *mut c_char = ptr::null_mut();
+        let len = unsafe { meta_data_toc(self.md, &mut toc) };
+        if len < 0 {
+            return Err(format_err!("meta_data_toc returned an error: {}", len));
+        }
+
+        if toc.is_null() {
+            return Ok(Vec::new());
+        }
+
+        // Every key is copied out of collectd, so all of them are freed (along with the table)
+        // before any error is reported
+        let keys: Vec<Result<String, Error>> = unsafe {
+            slice::from_raw_parts(toc, len as usize)
+                .iter()
+                .map(|&x| {
+                    let key = CStr::from_ptr(x)
+                        .to_str()
+                        .map(String::from)
+                        .context("meta data key is not UTF-8")
+                        .map_err(Error::from);
+                    free(x as *mut c_void);
+                    key
+                })
+                .collect()
+        };
+
+        unsafe {
+            free(toc as *mut c_void);
+        }
+
+        keys.into_iter().collect()
+    }
+
+    /// Returns if there is an entry with the key
+    pub fn contains_key(&self, key: &str) -> bool {
+        match CString::new(key) {
+            Ok(ref key) if !self.md.is_null() => unsafe {
+                meta_data_exists(self.md, key.as_ptr()) > 0
+            },
+            Ok(_) => self.owned.iter().any(|x| x.0.eq_ignore_ascii_case(key)),
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the value of the entry with the key, whatever its type
+    pub fn get(&self, key: &str) -> Result<Option<OwnedMetaValue>, Error> {
+        let name = CString::new(key).context("meta data key")?;
+        if self.md.is_null() {
+            return Ok(self.owned_entry(key, None)?.cloned());
+        }
+
+        let kind = unsafe { meta_data_type(self.md, name.as_ptr()) };
+
+        let value = match kind as u32 {
+            0 => None,
+            MD_TYPE_STRING => self.get_string(key)?.map(OwnedMetaValue::String),
+            MD_TYPE_SIGNED_INT => self.get_signed_int(key)?.map(OwnedMetaValue::SignedInt),
+            MD_TYPE_UNSIGNED_INT => self.get_unsigned_int(key)?.map(OwnedMetaValue::UnsignedInt),
+            MD_TYPE_DOUBLE => self.get_double(key)?.map(OwnedMetaValue::Double),
+            MD_TYPE_BOOLEAN => self.get_boolean(key)?.map(OwnedMetaValue::Boolean),
+            x => {
+                return Err(format_err!(
+                    "Unrecognized meta data type: {} for {}",
+                    x,
+                    key
+                ))
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// Returns the value of the string entry with the key
+    pub fn get_string(&self, key: &str) -> Result<Option<String>, Error> {
+        if self.md.is_null() {
+            return match self.owned_entry(key, Some(MD_TYPE_STRING))?.cloned() {
+                Some(OwnedMetaValue::String(x)) => Ok(Some(x)),
+                _ => Ok(None),
+            };
+        }
+
+        let mut value: *mut c_char = ptr::null_mut();
+        if !self.lookup(key, MD_TYPE_STRING, |md, k| unsafe {
+            meta_data_get_string(md, k, &mut value)
+        })? {
+            return Ok(None);
+        }
+
+        let result = unsafe { CStr::from_ptr(value) }
+            .to_str()
+            .map(String::from)
+            .with_context(|_e| format!("meta data {} is not UTF-8", key));
+        unsafe {
+            free(value as *mut c_void);
+        }
+
+        Ok(Some(result?))
+    }
+
+    /// Returns the value of the signed integer entry with the key
+    pub fn get_signed_int(&self, key: &str) -> Result<Option<i64>, Error> {
+        if self.md.is_null() {
+            return match self.owned_entry(key, Some(MD_TYPE_SIGNED_INT))? {
+                Some(&OwnedMetaValue::SignedInt(x)) => Ok(Some(x)),
+                _ => Ok(None),
+            };
+        }
+
+        let mut value = 0;
+        let found = self.lookup(key, MD_TYPE_SIGNED_INT, |md, k| unsafe {
+            meta_data_get_signed_int(md, k, &mut value)
+        })?;
+        Ok(if found { Some(value) } else { None })
+    }
+
+    /// Returns the value of the unsigned integer entry with the key
+    pub fn get_unsigned_int(&self, key: &str) -> Result<Option<u64>, Error> {
+        if self.md.is_null() {
+            return match self.owned_entry(key, Some(MD_TYPE_UNSIGNED_INT))? {
+                Some(&OwnedMetaValue::UnsignedInt(x)) => Ok(Some(x)),
+                _ => Ok(None),
+            };
+        }
+
+        let mut value = 0;
+        let found = self.lookup(key, MD_TYPE_UNSIGNED_INT, |md, k| unsafe {
+            meta_data_get_unsigned_int(md, k, &mut value)
+        })?;
+        Ok(if found { Some(value) } else { None })
+    }
+
+    /// Returns the value of the double entry with the key
+    pub fn get_double(&self, key: &str) -> Result<Option<f64>, Error> {
+        if self.md.is_null() {
+            return match self.owned_entry(key, Some(MD_TYPE_DOUBLE))? {
+                Some(&OwnedMetaValue::Double(x)) => Ok(Some(x)),
+                _ => Ok(None),
+            };
+        }
+
+        let mut value = 0.0;
+        let found = self.lookup(key, MD_TYPE_DOUBLE, |md, k| unsafe {
+            meta_data_get_double(md, k, &mut value)
+        })?;
+        Ok(if found { Some(value) } else { None })
+    }
+
+    /// Returns the value of the boolean entry with the key
+    pub fn get_boolean(&self, key: &str) -> Result<Option<bool>, Error> {
+        if self.md.is_null() {
+            return match self.owned_entry(key, Some(MD_TYPE_BOOLEAN))? {
+                Some(&OwnedMetaValue::Boolean(x)) => Ok(Some(x)),
+                _ => Ok(None),
+            };
+        }
+
+        let mut value = false;
+        let found = self.lookup(key, MD_TYPE_BOOLEAN, |md, k| unsafe {
+            meta_data_get_boolean(md, k, &mut value)
+        })?;
+        Ok(if found { Some(value) } else { None })
+    }
+
+    /// Returns all entries, ordered by key
+    pub fn entries(&self) -> Result<Vec<(String, OwnedMetaValue)>, Error> {
+        let mut keys = self.keys()?;
+        keys.sort();
+
+        let mut result = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(value) = self.get(&key)? {
+                result.push((key, value));
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Iterates over all entries, in the order that collectd stores them. The keys are read up
+    /// front, but each value is only copied out of collectd once it is reached.
+    pub fn iter(&self) -> MetaDataIter<'a> {
+        let (keys, error) = match self.keys() {
+            Ok(keys) => (keys, None),
+            Err(e) => (Vec::new(), Some(e)),
+        };
+
+        MetaDataIter {
+            meta: *self,
+            keys: keys.into_iter(),
+            error,
+        }
+    }
+
+    /// Finds the copied out entry with the key, which must be of the expected type if one is given
+    fn owned_entry(
+        &self,
+        key: &str,
+        expected: Option<u32>,
+    ) -> Result<Option<&'a OwnedMetaValue>, Error> {
+        let value = match self
+            .owned
+            .iter()
+            .find(|x| x.0.eq_ignore_ascii_case(key))
+            .map(|x| &x.1)
+        {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        match expected {
+            Some(kind) if kind != value.md_type() => Err(format_err!(
+                "meta data {} has a type of {}, not {}",
+                key,
+                value.md_type(),
+                kind
+            )),
+            _ => Ok(Some(value)),
+        }
+    }
+
+    /// Reads the entry with the key through the getter, if the entry exists. An entry of another
+    /// type is an error, as collectd doesn't convert between types.
+    fn lookup<F>(&self, key: &str, expected: u32, get: F) -> Result<bool, Error>
+    where
+        F: FnOnce(*mut meta_data_t, *const c_char) -> c_int,
+    {
+        let name = CString::new(key).context("meta data key")?;
+        if self.md.is_null() {
+            return Ok(false);
+        }
+
+        match unsafe { meta_data_type(self.md, name.as_ptr()) } {
+            0 => return Ok(false),
+            x if x as u32 != expected => {
+                return Err(format_err!(
+                    "meta data {} has a type of {}, not {}",
+                    key,
+                    x,
+                    expected
+                ))
+            }
+            _ => {}
+        }
+
+        match get(self.md, name.as_ptr()) {
+            0 => Ok(true),
+            x => Err(format_err!("unable to get meta data {}: {}", key, x)),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a MetaData<'a> {
+    type Item = Result<(String, OwnedMetaValue), Error>;
+    type IntoIter = MetaDataIter<'a>;
+
+    fn into_iter(self) -> MetaDataIter<'a> {
+        self.iter()
+    }
+}
+
+/// An iterator over the entries of `MetaData`, created with `MetaData::iter`. An entry that can't
+/// be decoded is yielded as an error, and iteration may continue past it.
+pub struct MetaDataIter<'a> {
+    meta: MetaData<'a>,
+    keys: IntoIter<String>,
+    error: Option<Error>,
+}
+
+impl<'a> Iterator for MetaDataIter<'a> {
+    type Item = Result<(String, OwnedMetaValue), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+
+        // An entry may have been removed since the keys were read, in which case it is skipped
+        loop {
+            let key = self.keys.next()?;
+            match self.meta.get(&key) {
+                Ok(Some(value)) => return Some(Ok((key, value))),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Owns collectd meta data that is built from typed entries. The meta data is destroyed on drop,
+/// which is safe once values have been dispatched, as collectd keeps its own copy.
+pub struct SubmitMetaData {
+    md: *mut meta_data_t,
+}
+
+impl SubmitMetaData {
+    /// Creates meta data containing the entries. No meta data is allocated if there are no entries
+    pub fn from_entries(entries: &[(&str, MetaValue)]) -> Result<SubmitMetaData, Error> {
+        if entries.is_empty() {
+            return Ok(SubmitMetaData {
+                md: ptr::null_mut(),
+            });
+        }
+
+        let md = unsafe { meta_data_create() };
+        if md.is_null() {
+            return Err(SubmitError::ValueMetaError(String::from("meta_data_create")).into());
+        }
+
+        // Wrap the meta data right away, so it is destroyed if an entry fails to be added
+        let result = SubmitMetaData { md };
+        for &(key, value) in entries {
+            let name = CString::new(key).context("value list meta key")?;
+            let res = unsafe {
+                match value {
+                    MetaValue::String(x) => {
+                        let value = CString::new(x)
+                            .with_context(|_e| format!("value list meta {}", key))?;
+                        meta_data_add_string(md, name.as_ptr(), value.as_ptr())
+                    }
+                    MetaValue::SignedInt(x) => meta_data_add_signed_int(md, name.as_ptr(), x),
+                    MetaValue::UnsignedInt(x) => meta_data_add_unsigned_int(md, name.as_ptr(), x),
+                    MetaValue::Double(x) => meta_data_add_double(md, name.as_ptr(), x),
+                    MetaValue::Boolean(x) => meta_data_add_boolean(md, name.as_ptr(), x),
+                }
+            };
+
+            if res != 0 {
+                return Err(SubmitError::ValueMetaError(key.to_string()).into());
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Pointer to the meta data, which is null if there are no entries
+    pub fn as_ptr(&self) -> *mut meta_data_t {
+        self.md
+    }
+}
+
+impl Drop for SubmitMetaData {
+    fn drop(&mut self) {
+        if !self.md.is_null() {
+            unsafe {
+                meta_data_destroy(self.md);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meta_data_from_entries() {
+        let md = SubmitMetaData::from_entries(&[]).unwrap();
+        assert!(md.as_ptr().is_null());
+
+        let md = SubmitMetaData::from_entries(&[
+            ("tag", MetaValue::String("prod")),
+            ("count", MetaValue::UnsignedInt(10)),
+            ("routed", MetaValue::Boolean(true)),
+        ])
+        .unwrap();
+        assert!(!md.as_ptr().is_null());
+
+        assert!(SubmitMetaData::from_entries(&[("t\0ag", MetaValue::SignedInt(1))]).is_err());
+        assert!(SubmitMetaData::from_entries(&[("tag", MetaValue::String("pr\0od"))]).is_err());
+    }
+
+    #[test]
+    fn test_meta_data_view() {
+        let submitted = SubmitMetaData::from_entries(&[
+            ("tag", MetaValue::String("prod")),
+            ("shard", MetaValue::SignedInt(-2)),
+            ("count", MetaValue::UnsignedInt(10)),
+            ("ratio", MetaValue::Double(0.5)),
+            ("routed", MetaValue::Boolean(true)),
+        ])
+        .unwrap();
+
+        let md = MetaData::from_ptr(submitted.as_ptr());
+        assert!(md.contains_key("tag"));
+        assert!(md.contains_key("TAG"));
+        assert!(!md.contains_key("missing"));
+        assert_eq!(md.get_string("tag").unwrap(), Some(String::from("prod")));
+        assert_eq!(md.get_signed_int("shard").unwrap(), Some(-2));
+        assert_eq!(md.get_unsigned_int("count").unwrap(), Some(10));
+        assert_eq!(md.get_double("ratio").unwrap(), Some(0.5));
+        assert_eq!(md.get_boolean("routed").unwrap(), Some(true));
+        assert_eq!(md.get_boolean("missing").unwrap(), None);
+        assert!(md.get_boolean("tag").is_err());
+
+        assert_eq!(
+            md.entries().unwrap(),
+            vec![
+                (String::from("count"), OwnedMetaValue::UnsignedInt(10)),
+                (String::from("ratio"), OwnedMetaValue::Double(0.5)),
+                (String::from("routed"), OwnedMetaValue::Boolean(true)),
+                (String::from("shard"), OwnedMetaValue::SignedInt(-2)),
+                (
+                    String::from("tag"),
+                    OwnedMetaValue::String(String::from("prod"))
+                ),
+            ]
+        );
+
+        let entries = md.entries().unwrap();
+        let owned = MetaData::from_entries(&entries);
+        assert_eq!(owned, md);
+        assert!(owned.contains_key("tag"));
+        assert!(owned.contains_key("Tag"));
+        assert_eq!(owned.keys().unwrap().len(), 5);
+        assert_eq!(
+            owned.get("shard").unwrap(),
+            Some(OwnedMetaValue::SignedInt(-2))
+        );
+        assert_eq!(owned.get_string("tag").unwrap(), Some(String::from("prod")));
+        assert_eq!(owned.get_string("TAG").unwrap(), Some(String::from("prod")));
+        assert_eq!(owned.get_double("ratio").unwrap(), Some(0.5));
+        assert_eq!(owned.get_double("missing").unwrap(), None);
+        assert!(owned.get_boolean("tag").is_err());
+        assert_eq!(owned.entries().unwrap(), entries);
+
+        let mut keys: Vec<String> = md.iter().map(|x| x.unwrap().0).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["count", "ratio", "routed", "shard", "tag"]);
+
+        let mut iterated = Vec::new();
+        for entry in &owned {
+            iterated.push(entry.unwrap());
+        }
+        assert_eq!(iterated, entries);
+
+        let empty = MetaData::default();
+        assert_eq!(empty.iter().count(), 0);
+        assert_ne!(empty, md);
+        assert_eq!(empty, MetaData::from_ptr(ptr::null_mut()));
+        assert_eq!(empty.keys().unwrap(), Vec::<String>::new());
+        assert_eq!(empty.get("tag").unwrap(), None);
+    }
+
+    #[test]
+    fn test_meta_data_undecodable() {
+        let submitted =
+            SubmitMetaData::from_entries(&[("count", MetaValue::SignedInt(1))]).unwrap();
+        let md = submitted.as_ptr();
+        unsafe {
+            meta_data_add_string(
+                md,
+                b"tag\0".as_ptr() as *const c_char,
+                b"\xff\0".as_ptr() as *const c_char,
+            );
+        }
+
+        let md = MetaData::from_ptr(md);
+        assert!(md.entries().is_err());
+        assert_eq!(md, md);
+        assert_ne!(md, MetaData::default());
+
+        let mut entries: Vec<bool> = md.iter().map(|x| x.is_ok()).collect();
+        entries.sort();
+        assert_eq!(entries, vec![false, true]);
+    }
+
+    #[test]
+    fn test_owned_meta_value() {
+        let value = OwnedMetaValue::from(MetaValue::String("prod"));
+        assert_eq!(value.as_meta_value(), MetaValue::String("prod"));
+    }
+}
@@ -1,25 +1,44 @@
 use bindings::{
-    data_set_t, hostname_g, plugin_dispatch_values, plugin_log, value_list_t, value_t, ARR_LENGTH,
-    DS_TYPE_ABSOLUTE, DS_TYPE_COUNTER, DS_TYPE_DERIVE, DS_TYPE_GAUGE, LOG_DEBUG, LOG_ERR, LOG_INFO,
-    LOG_NOTICE, LOG_WARNING,
+    data_set_t, hostname_g, plugin_dispatch_missing, plugin_dispatch_values, plugin_get_interval,
+    plugin_log, plugin_read_cb, plugin_register_complex_read, plugin_register_config, user_data_t,
+    value_list_t, value_t, ARR_LENGTH, DS_TYPE_ABSOLUTE, DS_TYPE_COUNTER, DS_TYPE_DERIVE,
+    DS_TYPE_GAUGE, LOG_DEBUG, LOG_ERR, LOG_INFO, LOG_NOTICE, LOG_WARNING,
 };
 use chrono::prelude::*;
 use chrono::Duration;
 use errors::{ArrayError, SubmitError};
 use failure::{Error, ResultExt};
 use memchr::memchr;
+use std::collections::HashSet;
 use std::ffi::{CStr, CString};
 use std::fmt;
-use std::os::raw::c_char;
+use std::mem;
+use std::os::raw::{c_char, c_int};
 use std::ptr;
 use std::slice;
 use std::str::Utf8Error;
+use std::sync::Arc;
 
 pub use self::cdtime::{nanos_to_collectd, CdTime};
-pub use self::oconfig::{ConfigItem, ConfigValue};
-
+use self::data_set::check_values;
+pub use self::data_set::{DataSet, DataSource};
+pub use self::identifier::{flush_identifier, Identifier};
+use self::meta::SubmitMetaData;
+pub use self::meta::{MetaData, MetaDataIter, MetaValue, OwnedMetaValue};
+pub use self::multivalue::MultiValueListBuilder;
+pub use self::notification::{
+    Notification, NotificationBuilder, NotificationLevel, NotificationMeta,
+};
+pub use self::oconfig::{ConfigItem, ConfigValue, OwnedConfigItem, OwnedConfigValue};
+pub use self::thread::{spawn_thread, PluginContext, PluginThread};
 mod cdtime;
+mod data_set;
+mod identifier;
+mod meta;
+mod multivalue;
+mod notification;
 mod oconfig;
+mod thread;
 
 /// The available levels that collectd exposes to log messages.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -46,10 +65,10 @@ impl LogLevel {
     }
 }
 
+/// The kinds of values that collectd understands. See `Value` for a description of each kind.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 #[repr(u32)]
-#[allow(dead_code)]
-enum ValueType {
+pub enum ValueType {
     Counter = DS_TYPE_COUNTER,
     Gauge = DS_TYPE_GAUGE,
     Derive = DS_TYPE_DERIVE,
@@ -95,6 +114,18 @@ impl fmt::Display for Value {
     }
 }
 
+impl Value {
+    /// The kind of the value, which must match the kind of the data source it is submitted for
+    pub fn value_type(&self) -> ValueType {
+        match *self {
+            Value::Counter(_) => ValueType::Counter,
+            Value::Gauge(_) => ValueType::Gauge,
+            Value::Derive(_) => ValueType::Derive,
+            Value::Absolute(_) => ValueType::Absolute,
+        }
+    }
+}
+
 // Interestingly, I couldn't get `From<Value> for value_t` to work, as any attempts would reference
 // value_t's typedef of value_u.
 impl Into<value_t> for Value {
@@ -154,6 +185,10 @@ pub struct ValueList<'a> {
 
     /// The interval in which new values are to be expected
     pub interval: Duration,
+
+    /// The meta data attached to the values (eg: by the submitting plugin or a filter chain
+    /// target)
+    pub meta: MetaData<'a>,
 }
 
 impl<'a> ValueList<'a> {
@@ -205,10 +240,152 @@ impl<'a> ValueList<'a> {
                 .with_context(|_e| format!("For plugin: {}, host could not be decoded", p))?,
             time: CdTime::from(list.time).into(),
             interval: CdTime::from(list.interval).into(),
+            meta: MetaData::from_ptr(list.meta),
+        })
+    }
+
+    /// Copies the value list (including its meta data) out of collectd, so that it can outlive
+    /// the write callback (eg: to be buffered and written in batches)
+    pub fn to_owned(&self) -> Result<OwnedValueList, Error> {
+        self.copy_names(|name| Arc::from(name))
+    }
+
+    /// Copies the value list like `to_owned`, except the host, plugin, type, and data source names
+    /// are taken from the cache, so that value lists buffered together share them
+    pub fn to_owned_with(&self, names: &mut NameCache) -> Result<OwnedValueList, Error> {
+        self.copy_names(|name| names.get(name))
+    }
+
+    fn copy_names<F>(&self, mut names: F) -> Result<OwnedValueList, Error>
+    where
+        F: FnMut(&str) -> Arc<str>,
+    {
+        Ok(OwnedValueList {
+            values: self
+                .values
+                .iter()
+                .map(|x| OwnedValueReport {
+                    name: names(x.name),
+                    value: x.value,
+                    min: x.min,
+                    max: x.max,
+                })
+                .collect(),
+            plugin: names(self.plugin),
+            plugin_instance: self.plugin_instance.map(String::from),
+            type_: names(self.type_),
+            type_instance: self.type_instance.map(String::from),
+            host: names(self.host),
+            time: self.time,
+            interval: self.interval,
+            meta: self.meta.entries()?,
         })
     }
 }
 
+/// Shares the names that repeat across owned value lists (the host, plugin, type, and data
+/// sources), so that each distinct name is allocated once. A write plugin typically keeps one cache
+/// next to its buffer of value lists, and clears both after each flush.
+#[derive(Debug, Default, Clone)]
+pub struct NameCache {
+    names: HashSet<Arc<str>>,
+}
+
+impl NameCache {
+    /// Creates an empty cache
+    pub fn new() -> NameCache {
+        NameCache::default()
+    }
+
+    /// Returns the shared name, adding it to the cache if it hasn't been seen yet
+    pub fn get(&mut self, name: &str) -> Arc<str> {
+        if let Some(name) = self.names.get(name) {
+            return Arc::clone(name);
+        }
+
+        let name: Arc<str> = Arc::from(name);
+        self.names.insert(Arc::clone(&name));
+        name
+    }
+
+    /// The number of distinct names in the cache
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Returns true if the cache holds no names
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Forgets all names. Value lists that were already made keep their names.
+    pub fn clear(&mut self) {
+        self.names.clear();
+    }
+}
+
+/// An owned version of `ValueReport`
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedValueReport {
+    /// Name of the metric
+    pub name: Arc<str>,
+
+    /// The value reported
+    pub value: Value,
+
+    /// Minimum value seen in an interval
+    pub min: f64,
+
+    /// Maximum value seen in an interval
+    pub max: f64,
+}
+
+/// An owned version of `ValueList`, for plugins that hold on to values after the write callback
+/// returns. The names of the host, plugin, type, and data sources are reference counted, and can
+/// be shared through a `NameCache` as they repeat across value lists, so cloning a buffered value
+/// list is cheap.
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedValueList {
+    pub values: Vec<OwnedValueReport>,
+    pub plugin: Arc<str>,
+    pub plugin_instance: Option<String>,
+    pub type_: Arc<str>,
+    pub type_instance: Option<String>,
+    pub host: Arc<str>,
+    pub time: DateTime<Utc>,
+    pub interval: Duration,
+
+    /// The meta data entries, ordered by key
+    pub meta: Vec<(String, OwnedMetaValue)>,
+}
+
+impl OwnedValueList {
+    /// Borrows the value list as a `ValueList`, so that it can be given to code written for the
+    /// values collectd passes to the write callback
+    pub fn as_ref<'a>(&'a self) -> ValueList<'a> {
+        ValueList {
+            values: self
+                .values
+                .iter()
+                .map(|x| ValueReport {
+                    name: &x.name,
+                    value: x.value,
+                    min: x.min,
+                    max: x.max,
+                })
+                .collect(),
+            plugin: &self.plugin,
+            plugin_instance: self.plugin_instance.as_ref().map(AsRef::as_ref),
+            type_: &self.type_,
+            type_instance: self.type_instance.as_ref().map(AsRef::as_ref),
+            host: &self.host,
+            time: self.time,
+            interval: self.interval,
+            meta: MetaData::from_entries(&self.meta),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 struct SubmitValueList<'a> {
     values: &'a [Value],
@@ -219,6 +396,7 @@ struct SubmitValueList<'a> {
     host: Option<&'a str>,
     time: Option<DateTime<Utc>>,
     interval: Option<Duration>,
+    meta: Vec<(&'a str, MetaValue<'a>)>,
 }
 
 /// Creates a value list to report values to collectd.
@@ -241,6 +419,7 @@ impl<'a> ValueListBuilder<'a> {
                 host: None,
                 time: None,
                 interval: None,
+                meta: Vec::new(),
             },
         }
     }
@@ -288,17 +467,67 @@ impl<'a> ValueListBuilder<'a> {
         self
     }
 
-    /// Submits the observed values to collectd and returns errors if encountered
+    /// Attaches a typed entry of meta data to the values (eg: tags or routing hints for writers).
+    /// May be called any number of times.
+    pub fn metadata(mut self, key: &'a str, value: MetaValue<'a>) -> ValueListBuilder<'a> {
+        self.list.meta.push((key, value));
+        self
+    }
+
+    /// Submits the observed values to collectd and returns errors if encountered. The values are
+    /// checked against the data set of the type first, so that values that collectd would reject
+    /// (eg: a single gauge for the `load` type, which has three) are reported as a descriptive
+    /// error.
     pub fn submit(self) -> Result<(), Error> {
-        let mut v: Vec<value_t> = self.list.values.into_iter().map(|&x| x.into()).collect();
+        let mut v: Vec<value_t> = self.list.values.iter().map(|&x| x.into()).collect();
+        let mut list = self.list.to_value_list(&mut v)?;
+        check_values(&list, Some(self.list.values))?;
+
+        // Collectd copies the meta data when values are dispatched, so ours can be dropped after
+        let meta = SubmitMetaData::from_entries(&self.list.meta)?;
+        list.meta = meta.as_ptr();
+
+        match unsafe { plugin_dispatch_values(&list) } {
+            0 => Ok(()),
+            i => Err(SubmitError::DispatchError(i).into()),
+        }
+    }
+
+    /// Declares that the series identified by this builder has gone missing (eg: a disk was
+    /// unplugged or a container died), so that plugins with a `MISSING` capability are notified.
+    /// Values are not required. Unlike `submit`, collectd will not fill in the time and interval,
+    /// so they default to now and the plugin's interval.
+    pub fn submit_missing(self) -> Result<(), Error> {
+        let mut v: Vec<value_t> = self.list.values.iter().map(|&x| x.into()).collect();
+        let mut list = self.list.to_value_list(&mut v)?;
+        let meta = SubmitMetaData::from_entries(&self.list.meta)?;
+        list.meta = meta.as_ptr();
+
+        if list.time == 0 {
+            list.time = CdTime::from(Utc::now()).into();
+        }
+
+        if list.interval == 0 {
+            list.interval = unsafe { plugin_get_interval() };
+        }
+
+        match unsafe { plugin_dispatch_missing(&list) } {
+            0 => Ok(()),
+            i => Err(SubmitError::MissingError(i).into()),
+        }
+    }
+}
+
+impl<'a> SubmitValueList<'a> {
+    /// Converts into collectd's value list, which will point to the given values. Thus the values
+    /// need to outlive the returned value list.
+    fn to_value_list(&self, values: &mut [value_t]) -> Result<value_list_t, Error> {
         let plugin_instance = self
-            .list
             .plugin_instance
             .map(|x| to_array_res(x).context("plugin_instance"))
             .unwrap_or_else(|| Ok([0i8; ARR_LENGTH]))?;
 
         let type_instance = self
-            .list
             .type_instance
             .map(|x| to_array_res(x).context("type_instance"))
             .unwrap_or_else(|| Ok([0i8; ARR_LENGTH]))?;
@@ -307,39 +536,28 @@ impl<'a> ValueListBuilder<'a> {
         // an empty array will get replaced with the hostname. However, since we're collectd 5.5
         // compatible, we use hostname_g in both circumstances, as it is not harmful
         let host = self
-            .list
             .host
             .map(|x| to_array_res(x).context("host"))
             .unwrap_or_else(|| unsafe { Ok(hostname_g) })?;
 
         #[cfg(collectd57)]
-        let len = v.len();
+        let len = values.len();
 
         #[cfg(not(collectd57))]
-        let len = v.len() as i32;
+        let len = values.len() as i32;
 
-        let list = value_list_t {
-            values: v.as_mut_ptr(),
+        Ok(value_list_t {
+            values: values.as_mut_ptr(),
             values_len: len,
             plugin_instance,
-            plugin: to_array_res(self.list.plugin)?,
-            type_: to_array_res(self.list.type_)?,
+            plugin: to_array_res(self.plugin)?,
+            type_: to_array_res(self.type_)?,
             type_instance,
             host,
-            time: self.list.time.map(CdTime::from).unwrap_or(CdTime(0)).into(),
-            interval: self
-                .list
-                .interval
-                .map(CdTime::from)
-                .unwrap_or(CdTime(0))
-                .into(),
+            time: self.time.map(CdTime::from).unwrap_or(CdTime(0)).into(),
+            interval: self.interval.map(CdTime::from).unwrap_or(CdTime(0)).into(),
             meta: ptr::null_mut(),
-        };
-
-        match unsafe { plugin_dispatch_values(&list) } {
-            0 => Ok(()),
-            i => Err(SubmitError::DispatchError(i).into()),
-        }
+        })
     }
 }
 
@@ -347,8 +565,16 @@ impl<'a> ValueListBuilder<'a> {
 /// slice into array compatible with collectd's text fields. Be aware that `ARR_LENGTH` is 64
 /// before collectd 5.7
 fn to_array_res(s: &str) -> Result<[c_char; ARR_LENGTH], ArrayError> {
+    let mut arr = [0; ARR_LENGTH];
+    copy_to_array(s, &mut arr)?;
+    Ok(arr)
+}
+
+/// Copies a string slice into a zeroed, fixed sized collectd text field of any length (not all
+/// text fields are `ARR_LENGTH` long, eg: notification messages)
+fn copy_to_array(s: &str, arr: &mut [c_char]) -> Result<(), ArrayError> {
     // By checking if the length is greater than or *equal* to, we guarantee a trailing null
-    if s.len() >= ARR_LENGTH {
+    if s.len() >= arr.len() {
         return Err(ArrayError::TooLong(s.len()));
     }
 
@@ -361,9 +587,9 @@ fn to_array_res(s: &str) -> Result<[c_char; ARR_LENGTH], ArrayError> {
         return Err(ArrayError::NullPresent(ind, s.to_string()));
     }
 
-    let mut arr = [0; ARR_LENGTH];
+    let bytes = unsafe { &*(bytes as *const [u8] as *const [c_char]) };
     arr[0..bytes.len()].copy_from_slice(bytes);
-    Ok(unsafe { ::std::mem::transmute(arr) })
+    Ok(())
 }
 
 /// Turns a fixed size character array into string slice, if possible
@@ -490,11 +716,81 @@ pub fn get_default_interval<T>() -> *const T {
     ptr::null()
 }
 
+/// Signature of collectd's simple config callback, which receives a key and its value
+#[doc(hidden)]
+pub type ConfigCallback = unsafe extern "C" fn(key: *const c_char, val: *const c_char) -> c_int;
+
+/// Registers a simple config callback for the given keys. Collectd holds onto the keys (without
+/// copying them) for the lifetime of the daemon, so they are intentionally leaked.
+#[doc(hidden)]
+pub unsafe fn register_config(
+    name: *const c_char,
+    keys: &[&str],
+    callback: ConfigCallback,
+) -> c_int {
+    let keys: Vec<CString> = keys
+        .iter()
+        .map(|&x| CString::new(x).expect("Config key to not contain nulls"))
+        .collect();
+    let mut ptrs: Vec<*const c_char> = keys.iter().map(|x| x.as_ptr()).collect();
+    let len = ptrs.len() as c_int;
+    let res = plugin_register_config(name, Some(callback), ptrs.as_mut_ptr(), len);
+    mem::forget(keys);
+    mem::forget(ptrs);
+    res
+}
+
+/// Registers a read callback at the given interval (or the plugin's default interval if absent).
+/// Collectd 5.7 accepts the interval as a `cdtime_t`, where zero denotes the default interval.
+#[cfg(collectd57)]
+#[doc(hidden)]
+pub unsafe fn register_complex_read(
+    group: *const c_char,
+    name: *const c_char,
+    callback: plugin_read_cb,
+    interval: Option<Duration>,
+    user_data: &mut user_data_t,
+) -> c_int {
+    let interval = interval.map(|x| CdTime::from(x).into()).unwrap_or(0);
+    plugin_register_complex_read(group, name, callback, interval, user_data)
+}
+
+/// Registers a read callback at the given interval (or the plugin's default interval if absent).
+/// Before collectd 5.7 the interval is a pointer to a `timespec`, where null denotes the default
+/// interval.
+#[cfg(not(collectd57))]
+#[doc(hidden)]
+pub unsafe fn register_complex_read(
+    group: *const c_char,
+    name: *const c_char,
+    callback: plugin_read_cb,
+    interval: Option<Duration>,
+    user_data: &mut user_data_t,
+) -> c_int {
+    use bindings::timespec;
+
+    let ts = interval.map(|x| {
+        let secs = x.num_seconds();
+        let nanos = (x - Duration::seconds(secs)).num_nanoseconds().unwrap_or(0);
+        timespec {
+            tv_sec: secs as _,
+            tv_nsec: nanos as _,
+        }
+    });
+
+    let interval = ts
+        .as_ref()
+        .map(|x| x as *const timespec)
+        .unwrap_or(ptr::null());
+    plugin_register_complex_read(group, name, callback, interval, user_data)
+}
+
 #[cfg(test)]
 mod tests {
     use self::cdtime::nanos_to_collectd;
     use super::*;
-    use bindings::data_source_t;
+    use bindings::{data_source_t, meta_data_add_string, meta_data_create, meta_data_destroy};
+    use std::ffi::CString;
     use std::os::raw::c_char;
 
     #[test]
@@ -527,6 +823,63 @@ mod tests {
         assert_eq!(result.unwrap(), ());
     }
 
+    #[test]
+    fn test_submit_metadata() {
+        let values = vec![Value::Gauge(15.0), Value::Gauge(10.0), Value::Gauge(12.0)];
+        let result = ValueListBuilder::new("my-plugin", "load")
+            .values(&values)
+            .metadata("tag", MetaValue::String("prod"))
+            .metadata("shard", MetaValue::SignedInt(-2))
+            .submit();
+        assert_eq!(result.unwrap(), ());
+
+        let result = ValueListBuilder::new("my-plugin", "load")
+            .values(&values)
+            .metadata("tag", MetaValue::String("pr\0od"))
+            .submit();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_submit_mismatched_values() {
+        let values = vec![Value::Gauge(15.0)];
+        let err = ValueListBuilder::new("my-plugin", "load")
+            .values(&values)
+            .submit()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "my-plugin submitted 1 values of type load, which has 3 data sources"
+        );
+
+        let values = vec![Value::Derive(10), Value::Gauge(12.0)];
+        let err = ValueListBuilder::new("my-plugin", "disk_octets")
+            .values(&values)
+            .submit()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "my-plugin submitted a Gauge for data source write of type disk_octets, which is a Derive"
+        );
+
+        let err = ValueListBuilder::new("my-plugin", "my_type")
+            .values(&values)
+            .submit()
+            .unwrap_err();
+        match err.downcast::<SubmitError>().unwrap() {
+            SubmitError::UnknownTypeError { type_, .. } => assert_eq!(type_, "my_type"),
+            x => panic!("unexpected error: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_submit_missing() {
+        let result = ValueListBuilder::new("my-plugin", "disk_octets")
+            .plugin_instance("sda")
+            .submit_missing();
+        assert_eq!(result.unwrap(), ());
+    }
+
     #[test]
     fn test_recv_value_list_conversion() {
         let empty: [c_char; ARR_LENGTH] = [0; ARR_LENGTH];
@@ -555,6 +908,13 @@ mod tests {
 
         let mut vs = vec![value_t { gauge: 3.0 }];
 
+        let key = CString::new("tag").unwrap();
+        let tag = CString::new("prod").unwrap();
+        let md = unsafe { meta_data_create() };
+        unsafe {
+            meta_data_add_string(md, key.as_ptr(), tag.as_ptr());
+        }
+
         let list_t = value_list_t {
             values: vs.as_mut_ptr(),
             values_len: 1,
@@ -565,9 +925,14 @@ mod tests {
             plugin_instance: metric,
             type_: metric,
             type_instance: empty,
-            meta: ptr::null_mut(),
+            meta: md,
         };
 
+        let entries = vec![(
+            String::from("tag"),
+            OwnedMetaValue::String(String::from("prod")),
+        )];
+
         let actual = ValueList::from(&conv, &list_t).unwrap();
         assert_eq!(
             actual,
@@ -585,7 +950,34 @@ mod tests {
                 host: "ho",
                 time: Utc.ymd(1970, 1, 1).and_hms(0, 0, 1),
                 interval: Duration::seconds(1),
+                meta: MetaData::from_entries(&entries),
             }
         );
+
+        let mut names = NameCache::new();
+        let owned = actual.to_owned_with(&mut names).unwrap();
+        assert_eq!(&*owned.plugin, "hi");
+        assert_eq!(owned.type_instance, None);
+        assert_eq!(owned.as_ref(), actual);
+        assert_eq!(actual.to_owned().unwrap(), owned);
+
+        // Names repeat across value lists, so they are shared rather than copied again
+        let other = actual.to_owned_with(&mut names).unwrap();
+        assert_eq!(names.len(), 2);
+        assert!(Arc::ptr_eq(&owned.host, &other.host));
+        assert!(Arc::ptr_eq(&owned.plugin, &other.plugin));
+        assert!(Arc::ptr_eq(&owned.type_, &other.type_));
+        assert!(Arc::ptr_eq(&owned.values[0].name, &other.values[0].name));
+        assert!(Arc::ptr_eq(&owned.host, &owned.type_));
+
+        unsafe {
+            meta_data_destroy(md);
+        }
+    }
+
+    #[test]
+    fn test_value_list_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<ValueList>();
     }
 }
@@ -0,0 +1,266 @@
+use super::data_set::check_values;
+use super::meta::SubmitMetaData;
+use super::{to_array_res, MetaValue, SubmitValueList, Value};
+use bindings::{plugin_dispatch_values, value_t};
+use chrono::prelude::*;
+use chrono::Duration;
+use errors::SubmitError;
+use failure::{Error, ResultExt};
+use std::f64;
+
+/// Creates many value lists that share a plugin and type, and differ only in their type instance
+/// (eg: the "free", "used", and "cached" gauges of the memory plugin), to report to collectd in
+/// one call. This is the equivalent of collectd's `plugin_dispatch_multivalue`, which as a
+/// variadic function can't be given a list of values from Rust, so the builder does the same
+/// work: the identifier is copied into collectd's value list once, all values share a timestamp,
+/// and then each value is dispatched under its type instance.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MultiValueListBuilder<'a> {
+    list: SubmitValueList<'a>,
+    values: Vec<(&'a str, Value)>,
+    store_percentage: bool,
+}
+
+impl<'a> MultiValueListBuilder<'a> {
+    /// Primes value lists for submission. `plugin` will most likely be the name from the
+    /// `PluginManager` and `type_` is the datatype found in types.db, which should have a single
+    /// data source.
+    pub fn new<T: Into<&'a str>, U: Into<&'a str>>(
+        plugin: T,
+        type_: U,
+    ) -> MultiValueListBuilder<'a> {
+        MultiValueListBuilder {
+            list: SubmitValueList {
+                values: &[],
+                plugin_instance: None,
+                plugin: plugin.into(),
+                type_: type_.into(),
+                type_instance: None,
+                host: None,
+                time: None,
+                interval: None,
+                meta: Vec::new(),
+            },
+            values: Vec::new(),
+            store_percentage: false,
+        }
+    }
+
+    /// Adds an observed value that is reported under the type instance. May be called any number
+    /// of times.
+    pub fn value<T: Into<&'a str>>(
+        mut self,
+        type_instance: T,
+        value: Value,
+    ) -> MultiValueListBuilder<'a> {
+        self.values.push((type_instance.into(), value));
+        self
+    }
+
+    /// Adds observed values, each paired with the type instance that it is reported under
+    pub fn values(mut self, values: &[(&'a str, Value)]) -> MultiValueListBuilder<'a> {
+        self.values.extend_from_slice(values);
+        self
+    }
+
+    /// Reports each value as its percentage of the sum of all values, under the "percent" type
+    /// instead of the builder's type (eg: to report memory usage as a percent of total memory).
+    /// Only gauges can be stored as a percentage.
+    pub fn store_percentage(mut self) -> MultiValueListBuilder<'a> {
+        self.store_percentage = true;
+        self
+    }
+
+    /// Distinguishes entities that yield metrics. Each core would be a different instance of the
+    /// same plugin, as each core reports "idle", "user", "system" metrics.
+    pub fn plugin_instance<T: Into<&'a str>>(
+        mut self,
+        plugin_instance: T,
+    ) -> MultiValueListBuilder<'a> {
+        self.list.plugin_instance = Some(plugin_instance.into());
+        self
+    }
+
+    /// Override the machine's hostname that the observed values will be attributed to
+    pub fn host<T: Into<&'a str>>(mut self, host: T) -> MultiValueListBuilder<'a> {
+        self.list.host = Some(host.into());
+        self
+    }
+
+    /// The timestamp at which the values were collected. Defaults to when the values are
+    /// submitted.
+    pub fn time(mut self, dt: DateTime<Utc>) -> MultiValueListBuilder<'a> {
+        self.list.time = Some(dt);
+        self
+    }
+
+    /// The interval in which new values are to be expected. This is typically handled at a global
+    /// or plugin level. Use at your own discretion.
+    pub fn interval(mut self, interval: Duration) -> MultiValueListBuilder<'a> {
+        self.list.interval = Some(interval);
+        self
+    }
+
+    /// Attaches a typed entry of meta data to every value. May be called any number of times.
+    pub fn metadata(mut self, key: &'a str, value: MetaValue<'a>) -> MultiValueListBuilder<'a> {
+        self.list.meta.push((key, value));
+        self
+    }
+
+    /// Submits the observed values to collectd. All values are checked against the data set of the
+    /// type (which is looked up once), and all type instances are converted for collectd, before
+    /// any value is dispatched, so a mismatch or an invalid type instance means that nothing was
+    /// dispatched. Values are then dispatched in the order that they were added, and
+    /// submission stops at the first value that collectd fails to dispatch, leaving the values
+    /// before it dispatched.
+    pub fn submit(mut self) -> Result<(), Error> {
+        let values = if self.store_percentage {
+            self.list.type_ = "percent";
+            percentages(&self.values)?
+        } else {
+            self.values
+        };
+
+        // Timestamp the values now, rather than as collectd receives each of them, so that all
+        // values belong to the same observation
+        if self.list.time.is_none() {
+            self.list.time = Some(Utc::now());
+        }
+
+        let mut v = [value_t { gauge: 0.0 }];
+        let mut list = self.list.to_value_list(&mut v)?;
+        check_values(&list, values.iter().map(|x| [x.1]))?;
+
+        let mut type_instances = Vec::with_capacity(values.len());
+        for &(type_instance, _) in &values {
+            type_instances.push(to_array_res(type_instance).context("type_instance")?);
+        }
+
+        let meta = SubmitMetaData::from_entries(&self.list.meta)?;
+        list.meta = meta.as_ptr();
+
+        for (type_instance, (_, value)) in type_instances.into_iter().zip(values) {
+            list.type_instance = type_instance;
+            unsafe {
+                *list.values = value.into();
+            }
+
+            match unsafe { plugin_dispatch_values(&list) } {
+                0 => {}
+                i => return Err(SubmitError::DispatchError(i).into()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Converts each gauge into its percentage of the sum of all gauges. As in collectd, gauges that
+/// are NaN don't count towards the sum, and all values are NaN when the sum is zero.
+fn percentages<'a>(values: &[(&'a str, Value)]) -> Result<Vec<(&'a str, Value)>, Error> {
+    let mut gauges = Vec::with_capacity(values.len());
+    for &(type_instance, value) in values {
+        match value {
+            Value::Gauge(x) => gauges.push((type_instance, x)),
+            _ => return Err(SubmitError::PercentageError(type_instance.to_string()).into()),
+        }
+    }
+
+    let sum: f64 = gauges.iter().map(|x| x.1).filter(|x| !x.is_nan()).sum();
+    let result = gauges
+        .into_iter()
+        .map(|(type_instance, x)| {
+            let percent = if sum != 0.0 {
+                x * 100.0 / sum
+            } else {
+                f64::NAN
+            };
+            (type_instance, Value::Gauge(percent))
+        })
+        .collect();
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bindings::overrides::stub_dispatched;
+
+    #[test]
+    fn test_percentages() {
+        let values = [
+            ("used", Value::Gauge(30.0)),
+            ("free", Value::Gauge(10.0)),
+            ("unknown", Value::Gauge(f64::NAN)),
+        ];
+
+        let actual = percentages(&values).unwrap();
+        assert_eq!(actual[0], ("used", Value::Gauge(75.0)));
+        assert_eq!(actual[1], ("free", Value::Gauge(25.0)));
+        match actual[2].1 {
+            Value::Gauge(x) => assert!(x.is_nan()),
+            ref x => panic!("unexpected value: {:?}", x),
+        }
+
+        let zeroes = percentages(&[("used", Value::Gauge(0.0))]).unwrap();
+        match zeroes[0].1 {
+            Value::Gauge(x) => assert!(x.is_nan()),
+            ref x => panic!("unexpected value: {:?}", x),
+        }
+
+        assert!(percentages(&[("used", Value::Derive(1))]).is_err());
+    }
+
+    #[test]
+    fn test_submit_multivalue() {
+        let dispatched = stub_dispatched();
+        let result = MultiValueListBuilder::new("memory", "memory")
+            .value("used", Value::Gauge(30.0))
+            .values(&[("free", Value::Gauge(10.0)), ("cached", Value::Gauge(5.0))])
+            .metadata("tag", MetaValue::String("prod"))
+            .submit();
+        assert!(result.is_ok());
+        assert_eq!(stub_dispatched(), dispatched + 3);
+
+        let result = MultiValueListBuilder::new("memory", "memory")
+            .value("used", Value::Derive(30))
+            .store_percentage()
+            .submit();
+        assert!(result.is_err());
+
+        // An invalid type instance is found before the first value is dispatched
+        let dispatched = stub_dispatched();
+        let result = MultiValueListBuilder::new("memory", "memory")
+            .value("used", Value::Gauge(30.0))
+            .value("fr\0ee", Value::Gauge(10.0))
+            .submit();
+        assert!(result.is_err());
+        assert_eq!(stub_dispatched(), dispatched);
+
+        let result = MultiValueListBuilder::new("memory", "memory")
+            .value("used", Value::Derive(30))
+            .submit();
+        assert!(result.is_err());
+
+        // The mismatch of the last value is found before the first value is dispatched
+        let dispatched = stub_dispatched();
+        let err = MultiValueListBuilder::new("memory", "memory")
+            .value("used", Value::Gauge(30.0))
+            .value("free", Value::Derive(10))
+            .submit()
+            .unwrap_err();
+        match err.downcast::<SubmitError>().unwrap() {
+            SubmitError::ValueTypeError { name, .. } => assert_eq!(name, "value"),
+            x => panic!("unexpected error: {:?}", x),
+        }
+        assert_eq!(stub_dispatched(), dispatched);
+
+        let result = MultiValueListBuilder::new("memory", "memory")
+            .value("used", Value::Gauge(30.0))
+            .value("free", Value::Gauge(10.0))
+            .store_percentage()
+            .submit();
+        assert!(result.is_ok());
+    }
+}
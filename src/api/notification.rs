@@ -0,0 +1,421 @@
+use super::{copy_to_array, empty_to_none, from_array, to_array_res, CdTime, MetaValue};
+use bindings::{
+    hostname_g, notification_meta_t, notification_meta_type_e_NM_TYPE_BOOLEAN,
+    notification_meta_type_e_NM_TYPE_DOUBLE, notification_meta_type_e_NM_TYPE_SIGNED_INT,
+    notification_meta_type_e_NM_TYPE_STRING, notification_meta_type_e_NM_TYPE_UNSIGNED_INT,
+    notification_t, plugin_dispatch_notification, plugin_notification_meta_add_boolean,
+    plugin_notification_meta_add_double, plugin_notification_meta_add_signed_int,
+    plugin_notification_meta_add_string, plugin_notification_meta_add_unsigned_int,
+    plugin_notification_meta_free, ARR_LENGTH, NOTIF_FAILURE, NOTIF_MAX_MSG_LEN, NOTIF_OKAY,
+    NOTIF_WARNING,
+};
+use chrono::prelude::*;
+use errors::SubmitError;
+use failure::{Error, ResultExt};
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+/// The severity of a notification
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u32)]
+pub enum NotificationLevel {
+    Failure = NOTIF_FAILURE,
+    Warning = NOTIF_WARNING,
+    Okay = NOTIF_OKAY,
+}
+
+impl NotificationLevel {
+    /// Attempts to convert a u32 representing a collectd notification severity into a Rust enum
+    pub fn try_from(s: u32) -> Option<NotificationLevel> {
+        match s {
+            NOTIF_FAILURE => Some(NotificationLevel::Failure),
+            NOTIF_WARNING => Some(NotificationLevel::Warning),
+            NOTIF_OKAY => Some(NotificationLevel::Okay),
+            _ => None,
+        }
+    }
+}
+
+/// Name and value of additional information attached to a notification
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct NotificationMeta<'a> {
+    pub name: &'a str,
+    pub value: MetaValue<'a>,
+}
+
+/// A notification that collectd has received from a plugin (eg: the threshold plugin reporting
+/// that a value is out of bounds)
+#[derive(Debug, PartialEq, Clone)]
+pub struct Notification<'a> {
+    /// How severe the notification is
+    pub severity: NotificationLevel,
+
+    /// The timestamp at which the notification was raised
+    pub time: DateTime<Utc>,
+
+    /// Human readable description of the notification
+    pub message: &'a str,
+
+    /// The hostname the notification is about
+    pub host: &'a str,
+
+    /// The plugin that the notification is about
+    pub plugin: Option<&'a str>,
+
+    /// The plugin instance that the notification is about
+    pub plugin_instance: Option<&'a str>,
+
+    /// The type that the notification is about
+    pub type_: Option<&'a str>,
+
+    /// The type instance that the notification is about
+    pub type_instance: Option<&'a str>,
+
+    /// Additional information attached to the notification
+    pub meta: Vec<NotificationMeta<'a>>,
+}
+
+impl<'a> Notification<'a> {
+    pub fn from<'b>(n: &'b notification_t) -> Result<Notification<'b>, Error> {
+        let severity = NotificationLevel::try_from(n.severity as u32)
+            .ok_or_else(|| format_err!("Unrecognized notification severity: {}", n.severity))?;
+
+        let message = unsafe { CStr::from_ptr(n.message.as_ptr()) }
+            .to_str()
+            .context("Notification message could not be decoded")?;
+
+        let mut meta = Vec::new();
+        let mut ptr = n.meta;
+        while !ptr.is_null() {
+            let m = unsafe { &*ptr };
+            meta.push(notification_meta(m)?);
+            ptr = m.next;
+        }
+
+        Ok(Notification {
+            severity,
+            time: CdTime::from(n.time).into(),
+            message,
+            host: from_array(&n.host).context("Notification host could not be decoded")?,
+            plugin: empty_to_none(
+                from_array(&n.plugin).context("Notification plugin could not be decoded")?,
+            ),
+            plugin_instance: empty_to_none(
+                from_array(&n.plugin_instance)
+                    .context("Notification plugin instance could not be decoded")?,
+            ),
+            type_: empty_to_none(
+                from_array(&n.type_).context("Notification type could not be decoded")?,
+            ),
+            type_instance: empty_to_none(
+                from_array(&n.type_instance)
+                    .context("Notification type instance could not be decoded")?,
+            ),
+            meta,
+        })
+    }
+}
+
+fn notification_meta<'a>(m: &'a notification_meta_t) -> Result<NotificationMeta<'a>, Error> {
+    let name = from_array(&m.name).context("Notification meta name could not be decoded")?;
+    let value = unsafe {
+        if m.type_ == notification_meta_type_e_NM_TYPE_STRING {
+            let s = CStr::from_ptr(m.nm_value.nm_string)
+                .to_str()
+                .with_context(|_e| format!("Notification meta {} could not be decoded", name))?;
+            MetaValue::String(s)
+        } else if m.type_ == notification_meta_type_e_NM_TYPE_SIGNED_INT {
+            MetaValue::SignedInt(m.nm_value.nm_signed_int)
+        } else if m.type_ == notification_meta_type_e_NM_TYPE_UNSIGNED_INT {
+            MetaValue::UnsignedInt(m.nm_value.nm_unsigned_int)
+        } else if m.type_ == notification_meta_type_e_NM_TYPE_DOUBLE {
+            MetaValue::Double(m.nm_value.nm_double)
+        } else if m.type_ == notification_meta_type_e_NM_TYPE_BOOLEAN {
+            MetaValue::Boolean(m.nm_value.nm_boolean)
+        } else {
+            return Err(format_err!(
+                "Unrecognized notification meta type: {}",
+                m.type_
+            ));
+        }
+    };
+
+    Ok(NotificationMeta { name, value })
+}
+
+/// Creates a notification to dispatch to collectd, which will be received by all plugins that
+/// registered a `NOTIFICATION` capability.
+#[derive(Debug, PartialEq, Clone)]
+pub struct NotificationBuilder<'a> {
+    severity: NotificationLevel,
+    message: &'a str,
+    plugin: &'a str,
+    plugin_instance: Option<&'a str>,
+    type_: Option<&'a str>,
+    type_instance: Option<&'a str>,
+    host: Option<&'a str>,
+    time: Option<DateTime<Utc>>,
+    meta: Vec<(&'a str, MetaValue<'a>)>,
+}
+
+impl<'a> NotificationBuilder<'a> {
+    /// Primes a notification for submission. `plugin` will most likely be the name from the
+    /// `PluginManager` and `message` is a human readable description of what happened. Be aware
+    /// that collectd limits messages to 256 bytes.
+    pub fn new<T: Into<&'a str>, U: Into<&'a str>>(
+        plugin: T,
+        severity: NotificationLevel,
+        message: U,
+    ) -> NotificationBuilder<'a> {
+        NotificationBuilder {
+            severity,
+            message: message.into(),
+            plugin: plugin.into(),
+            plugin_instance: None,
+            type_: None,
+            type_instance: None,
+            host: None,
+            time: None,
+            meta: Vec::new(),
+        }
+    }
+
+    /// Distinguishes the entity that the notification is about (eg: which RAID array degraded)
+    pub fn plugin_instance<T: Into<&'a str>>(
+        mut self,
+        plugin_instance: T,
+    ) -> NotificationBuilder<'a> {
+        self.plugin_instance = Some(plugin_instance.into());
+        self
+    }
+
+    /// The type (as found in types.db) that the notification is about
+    pub fn type_<T: Into<&'a str>>(mut self, type_: T) -> NotificationBuilder<'a> {
+        self.type_ = Some(type_.into());
+        self
+    }
+
+    /// The type instance that the notification is about
+    pub fn type_instance<T: Into<&'a str>>(mut self, type_instance: T) -> NotificationBuilder<'a> {
+        self.type_instance = Some(type_instance.into());
+        self
+    }
+
+    /// Override the machine's hostname that the notification will be attributed to
+    pub fn host<T: Into<&'a str>>(mut self, host: T) -> NotificationBuilder<'a> {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// The timestamp at which the notification was raised. Defaults to the time at which `submit`
+    /// is called.
+    pub fn time(mut self, dt: DateTime<Utc>) -> NotificationBuilder<'a> {
+        self.time = Some(dt);
+        self
+    }
+
+    /// Attaches a typed entry of additional information to the notification. May be called any
+    /// number of times.
+    pub fn metadata(mut self, key: &'a str, value: MetaValue<'a>) -> NotificationBuilder<'a> {
+        self.meta.push((key, value));
+        self
+    }
+
+    /// Dispatches the notification to collectd and returns errors if encountered. Collectd
+    /// considers it an error to dispatch a notification when no plugin is registered to receive
+    /// notifications.
+    pub fn submit(self) -> Result<(), Error> {
+        let mut message = [0; NOTIF_MAX_MSG_LEN as usize];
+        copy_to_array(self.message, &mut message).context("message")?;
+
+        let plugin_instance = self
+            .plugin_instance
+            .map(|x| to_array_res(x).context("plugin_instance"))
+            .unwrap_or_else(|| Ok([0i8; ARR_LENGTH]))?;
+
+        let type_ = self
+            .type_
+            .map(|x| to_array_res(x).context("type"))
+            .unwrap_or_else(|| Ok([0i8; ARR_LENGTH]))?;
+
+        let type_instance = self
+            .type_instance
+            .map(|x| to_array_res(x).context("type_instance"))
+            .unwrap_or_else(|| Ok([0i8; ARR_LENGTH]))?;
+
+        // Unlike values, collectd does not fill in the hostname for notifications
+        let host = self
+            .host
+            .map(|x| to_array_res(x).context("host"))
+            .unwrap_or_else(|| unsafe { Ok(hostname_g) })?;
+
+        let mut n = notification_t {
+            severity: self.severity as i32,
+            time: CdTime::from(self.time.unwrap_or_else(Utc::now)).into(),
+            message,
+            host,
+            plugin: to_array_res(self.plugin).context("plugin")?,
+            plugin_instance,
+            type_,
+            type_instance,
+            meta: ptr::null_mut(),
+        };
+
+        let result = add_meta(&mut n, &self.meta).and_then(|_| {
+            match unsafe { plugin_dispatch_notification(&n) } {
+                0 => Ok(()),
+                i => Err(SubmitError::NotificationError(i).into()),
+            }
+        });
+
+        // Collectd allocated the meta entries, so it is responsible for freeing them (and it logs
+        // an error if asked to free nothing)
+        if !n.meta.is_null() {
+            unsafe {
+                plugin_notification_meta_free(n.meta);
+            }
+        }
+
+        result
+    }
+}
+
+/// Attaches each meta entry to the notification. Collectd copies the names and values, so the
+/// intermediate C strings only need to live for the duration of the call
+fn add_meta(n: &mut notification_t, meta: &[(&str, MetaValue)]) -> Result<(), Error> {
+    for &(key, value) in meta {
+        let name = CString::new(key).context("notification meta name")?;
+        let res = unsafe {
+            match value {
+                MetaValue::String(x) => {
+                    let value =
+                        CString::new(x).with_context(|_e| format!("notification meta {}", key))?;
+                    plugin_notification_meta_add_string(n, name.as_ptr(), value.as_ptr())
+                }
+                MetaValue::SignedInt(x) => {
+                    plugin_notification_meta_add_signed_int(n, name.as_ptr(), x)
+                }
+                MetaValue::UnsignedInt(x) => {
+                    plugin_notification_meta_add_unsigned_int(n, name.as_ptr(), x)
+                }
+                MetaValue::Double(x) => plugin_notification_meta_add_double(n, name.as_ptr(), x),
+                MetaValue::Boolean(x) => plugin_notification_meta_add_boolean(n, name.as_ptr(), x),
+            }
+        };
+
+        if res != 0 {
+            return Err(SubmitError::NotificationMetaError(key.to_string()).into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::cdtime::nanos_to_collectd;
+    use bindings::notification_meta_s__bindgen_ty_1;
+    use std::os::raw::c_char;
+
+    fn to_arr(s: &str) -> [c_char; ARR_LENGTH] {
+        let mut arr: [c_char; ARR_LENGTH] = [0; ARR_LENGTH];
+        for (i, b) in s.bytes().enumerate() {
+            arr[i] = b as c_char;
+        }
+        arr
+    }
+
+    #[test]
+    fn test_notification_conversion() {
+        let empty: [c_char; ARR_LENGTH] = [0; ARR_LENGTH];
+        let mut message: [c_char; 256] = [0; 256];
+        message[0] = b'o' as c_char;
+        message[1] = b'k' as c_char;
+
+        let mut second = notification_meta_t {
+            name: to_arr("count"),
+            type_: notification_meta_type_e_NM_TYPE_UNSIGNED_INT,
+            nm_value: notification_meta_s__bindgen_ty_1 {
+                nm_unsigned_int: 10,
+            },
+            next: ptr::null_mut(),
+        };
+
+        let mut first = notification_meta_t {
+            name: to_arr("degraded"),
+            type_: notification_meta_type_e_NM_TYPE_BOOLEAN,
+            nm_value: notification_meta_s__bindgen_ty_1 { nm_boolean: true },
+            next: &mut second,
+        };
+
+        let n = notification_t {
+            severity: NOTIF_WARNING as i32,
+            time: nanos_to_collectd(1_000_000_000),
+            message,
+            host: to_arr("localhost"),
+            plugin: to_arr("raid"),
+            plugin_instance: to_arr("md0"),
+            type_: empty,
+            type_instance: empty,
+            meta: &mut first,
+        };
+
+        let actual = Notification::from(&n).unwrap();
+        assert_eq!(
+            actual,
+            Notification {
+                severity: NotificationLevel::Warning,
+                time: Utc.ymd(1970, 1, 1).and_hms(0, 0, 1),
+                message: "ok",
+                host: "localhost",
+                plugin: Some("raid"),
+                plugin_instance: Some("md0"),
+                type_: None,
+                type_instance: None,
+                meta: vec![
+                    NotificationMeta {
+                        name: "degraded",
+                        value: MetaValue::Boolean(true),
+                    },
+                    NotificationMeta {
+                        name: "count",
+                        value: MetaValue::UnsignedInt(10),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_notification_level() {
+        assert_eq!(
+            NotificationLevel::try_from(NOTIF_FAILURE),
+            Some(NotificationLevel::Failure)
+        );
+        assert_eq!(NotificationLevel::try_from(3), None);
+    }
+
+    #[test]
+    fn test_submit_notification() {
+        let result = NotificationBuilder::new("raid", NotificationLevel::Failure, "array degraded")
+            .plugin_instance("md0")
+            .metadata("disks", MetaValue::SignedInt(3))
+            .metadata("state", MetaValue::String("degraded"))
+            .submit();
+        assert_eq!(result.unwrap(), ());
+
+        let result = NotificationBuilder::new("raid", NotificationLevel::Okay, "array recovered")
+            .metadata("sta\0te", MetaValue::String("clean"))
+            .submit();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_submit_notification_message_too_long() {
+        let message = "a".repeat(300);
+        let result =
+            NotificationBuilder::new("raid", NotificationLevel::Okay, message.as_str()).submit();
+        assert!(result.is_err());
+    }
+}
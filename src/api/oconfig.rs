@@ -84,3 +84,98 @@ impl<'a> ConfigItem<'a> {
         })
     }
 }
+
+/// An owned version of `ConfigValue`, for when a config needs to outlive collectd's config
+/// callback
+#[derive(Debug, PartialEq, Clone)]
+pub enum OwnedConfigValue {
+    /// Numeric value
+    Number(f64),
+
+    /// True / false, on / off
+    Boolean(bool),
+
+    /// Contents enclosed in quote
+    String(String),
+}
+
+/// An owned version of `ConfigItem`, for when a config needs to outlive collectd's config callback
+#[derive(Debug, PartialEq, Clone)]
+pub struct OwnedConfigItem {
+    /// Key of the field, does not have to be unique
+    pub key: String,
+
+    /// Values on the same line as the key
+    pub values: Vec<OwnedConfigValue>,
+
+    /// Sub elements
+    pub children: Vec<OwnedConfigItem>,
+}
+
+impl<'a> From<&'a ConfigValue<'a>> for OwnedConfigValue {
+    fn from(value: &'a ConfigValue<'a>) -> OwnedConfigValue {
+        match *value {
+            ConfigValue::Number(x) => OwnedConfigValue::Number(x),
+            ConfigValue::Boolean(x) => OwnedConfigValue::Boolean(x),
+            ConfigValue::String(x) => OwnedConfigValue::String(x.to_string()),
+        }
+    }
+}
+
+impl<'a> From<&'a ConfigItem<'a>> for OwnedConfigItem {
+    fn from(item: &'a ConfigItem<'a>) -> OwnedConfigItem {
+        OwnedConfigItem {
+            key: item.key.to_string(),
+            values: item.values.iter().map(OwnedConfigValue::from).collect(),
+            children: item.children.iter().map(OwnedConfigItem::from).collect(),
+        }
+    }
+}
+
+impl OwnedConfigValue {
+    /// Borrows the owned value as a `ConfigValue`
+    pub fn as_config_value<'a>(&'a self) -> ConfigValue<'a> {
+        match *self {
+            OwnedConfigValue::Number(x) => ConfigValue::Number(x),
+            OwnedConfigValue::Boolean(x) => ConfigValue::Boolean(x),
+            OwnedConfigValue::String(ref x) => ConfigValue::String(x),
+        }
+    }
+}
+
+impl OwnedConfigItem {
+    /// Borrows the owned item as a `ConfigItem`
+    pub fn as_config_item<'a>(&'a self) -> ConfigItem<'a> {
+        ConfigItem {
+            key: &self.key,
+            values: self.values.iter().map(|x| x.as_config_value()).collect(),
+            children: self.children.iter().map(|x| x.as_config_item()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_owned_config_round_trip() {
+        let item = ConfigItem {
+            key: "Node",
+            values: vec![ConfigValue::String("local")],
+            children: vec![ConfigItem {
+                key: "Port",
+                values: vec![ConfigValue::Number(2003.0), ConfigValue::Boolean(true)],
+                children: vec![],
+            }],
+        };
+
+        let owned = OwnedConfigItem::from(&item);
+        assert_eq!(owned.key, "Node");
+        assert_eq!(
+            owned.values,
+            vec![OwnedConfigValue::String(String::from("local"))]
+        );
+        assert_eq!(owned.as_config_item(), item);
+    }
+}
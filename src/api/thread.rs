@@ -0,0 +1,167 @@
+use super::CdTime;
+use bindings::{plugin_ctx_t, plugin_get_ctx, plugin_set_ctx, plugin_thread_create, pthread_t};
+use chrono::Duration;
+use failure::{Error, ResultExt};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+extern "C" {
+    fn pthread_join(thread: pthread_t, retval: *mut *mut c_void) -> c_int;
+    fn pthread_detach(thread: pthread_t) -> c_int;
+}
+
+/// Collectd's per-thread plugin context, which holds the defaults (eg: the interval) for values
+/// dispatched from the thread. Collectd sets the context of the plugin before invoking any of its
+/// callbacks, and `spawn_thread` carries the context over to background threads.
+#[derive(Debug, Clone, Copy)]
+pub struct PluginContext {
+    ctx: plugin_ctx_t,
+}
+
+impl PluginContext {
+    /// Returns the context of the current thread
+    pub fn current() -> PluginContext {
+        PluginContext {
+            ctx: unsafe { plugin_get_ctx() },
+        }
+    }
+
+    /// The interval that values dispatched under this context default to
+    pub fn interval(&self) -> Duration {
+        CdTime::from(self.ctx.interval).into()
+    }
+
+    /// Makes this the context of the current thread, and returns the context that it replaced
+    pub fn set(&self) -> PluginContext {
+        PluginContext {
+            ctx: unsafe { plugin_set_ctx(self.ctx) },
+        }
+    }
+}
+
+/// A thread started with `spawn_thread`. Dropping the handle detaches the thread.
+pub struct PluginThread<T> {
+    thread: Option<pthread_t>,
+    result: Receiver<thread::Result<T>>,
+}
+
+impl<T> PluginThread<T> {
+    /// Waits for the thread to finish. If the thread panicked, the panic's payload is returned as
+    /// the error.
+    pub fn join(mut self) -> thread::Result<T> {
+        if let Some(thread) = self.thread.take() {
+            unsafe {
+                pthread_join(thread, ptr::null_mut());
+            }
+        }
+
+        self.result
+            .recv()
+            .unwrap_or_else(|_| Err(Box::new("thread exited without a result")))
+    }
+}
+
+impl<T> Drop for PluginThread<T> {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            unsafe {
+                pthread_detach(thread);
+            }
+        }
+    }
+}
+
+/// Spawns a thread through collectd that runs the given closure with the plugin context of the
+/// calling thread, so that values dispatched from the thread have the same defaults (eg: interval)
+/// as those dispatched from the plugin's callbacks. Spawn threads from within a callback (eg:
+/// `Plugin::initialize`), where collectd has set the plugin's context. The thread is given the
+/// name on collectd 5.7 and above.
+pub fn spawn_thread<F, T>(name: &str, f: F) -> Result<PluginThread<T>, Error>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let name = CString::new(name).context("thread name")?;
+    let (tx, rx) = mpsc::channel();
+    let thread = start_thread(&name, move || {
+        // The receiver may have been dropped if the thread was detached
+        let _ = tx.send(panic::catch_unwind(AssertUnwindSafe(f)));
+    })?;
+
+    Ok(PluginThread {
+        thread: Some(thread),
+        result: rx,
+    })
+}
+
+fn start_thread<F>(name: &CStr, f: F) -> Result<pthread_t, Error>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let arg = Box::into_raw(Box::new(f)) as *mut c_void;
+    let mut thread: pthread_t = 0;
+    match unsafe { create_thread(&mut thread, run_thread::<F>, arg, name) } {
+        0 => Ok(thread),
+        i => {
+            // The thread never started, so the closure is ours to drop
+            drop(unsafe { Box::from_raw(arg as *mut F) });
+            Err(format_err!("plugin_thread_create returned an error: {}", i))
+        }
+    }
+}
+
+unsafe extern "C" fn run_thread<F: FnOnce()>(arg: *mut c_void) -> *mut c_void {
+    let f = *Box::from_raw(arg as *mut F);
+    f();
+    ptr::null_mut()
+}
+
+#[cfg(collectd57)]
+unsafe fn create_thread(
+    thread: &mut pthread_t,
+    start: unsafe extern "C" fn(*mut c_void) -> *mut c_void,
+    arg: *mut c_void,
+    name: &CStr,
+) -> c_int {
+    plugin_thread_create(thread, ptr::null(), Some(start), arg, name.as_ptr())
+}
+
+#[cfg(not(collectd57))]
+unsafe fn create_thread(
+    thread: &mut pthread_t,
+    start: unsafe extern "C" fn(*mut c_void) -> *mut c_void,
+    arg: *mut c_void,
+    _name: &CStr,
+) -> c_int {
+    plugin_thread_create(thread, ptr::null(), Some(start), arg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plugin_context() {
+        let mut ctx = PluginContext::current();
+        ctx.ctx.interval = CdTime::from(Duration::seconds(10)).into();
+
+        let previous = ctx.set();
+        assert_eq!(PluginContext::current().interval(), Duration::seconds(10));
+
+        previous.set();
+        assert_eq!(PluginContext::current().interval(), previous.interval());
+    }
+
+    #[test]
+    fn test_spawn_thread() {
+        let thread = spawn_thread("test", || 1 + 1).unwrap();
+        assert_eq!(thread.join().unwrap(), 2);
+
+        let thread = spawn_thread("test", || -> i32 { panic!("oh no") }).unwrap();
+        assert!(thread.join().is_err());
+    }
+}
@@ -8,11 +8,20 @@ pub const DS_TYPE_COUNTER: ::std::os::raw::c_uint = 0;
 pub const DS_TYPE_GAUGE: ::std::os::raw::c_uint = 1;
 pub const DS_TYPE_DERIVE: ::std::os::raw::c_uint = 2;
 pub const DS_TYPE_ABSOLUTE: ::std::os::raw::c_uint = 3;
+pub const MD_TYPE_STRING: ::std::os::raw::c_uint = 1;
+pub const MD_TYPE_SIGNED_INT: ::std::os::raw::c_uint = 2;
+pub const MD_TYPE_UNSIGNED_INT: ::std::os::raw::c_uint = 3;
+pub const MD_TYPE_DOUBLE: ::std::os::raw::c_uint = 4;
+pub const MD_TYPE_BOOLEAN: ::std::os::raw::c_uint = 5;
 pub const LOG_ERR: ::std::os::raw::c_uint = 3;
 pub const LOG_WARNING: ::std::os::raw::c_uint = 4;
 pub const LOG_NOTICE: ::std::os::raw::c_uint = 5;
 pub const LOG_INFO: ::std::os::raw::c_uint = 6;
 pub const LOG_DEBUG: ::std::os::raw::c_uint = 7;
+pub const NOTIF_MAX_MSG_LEN: ::std::os::raw::c_uint = 256;
+pub const NOTIF_FAILURE: ::std::os::raw::c_uint = 1;
+pub const NOTIF_WARNING: ::std::os::raw::c_uint = 2;
+pub const NOTIF_OKAY: ::std::os::raw::c_uint = 4;
 pub type __time_t = ::std::os::raw::c_long;
 pub type __syscall_slong_t = ::std::os::raw::c_long;
 #[repr(C)]
@@ -283,6 +292,109 @@ pub struct meta_data_s {
     _unused: [u8; 0],
 }
 pub type meta_data_t = meta_data_s;
+extern "C" {
+    pub fn meta_data_create() -> *mut meta_data_t;
+}
+extern "C" {
+    pub fn meta_data_clone(orig: *mut meta_data_t) -> *mut meta_data_t;
+}
+extern "C" {
+    pub fn meta_data_destroy(md: *mut meta_data_t);
+}
+extern "C" {
+    pub fn meta_data_exists(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn meta_data_type(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn meta_data_toc(
+        md: *mut meta_data_t,
+        toc: *mut *mut *mut ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn meta_data_delete(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn meta_data_add_string(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn meta_data_add_signed_int(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: i64,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn meta_data_add_unsigned_int(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: u64,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn meta_data_add_double(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: f64,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn meta_data_add_boolean(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: bool,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn meta_data_get_string(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: *mut *mut ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn meta_data_get_signed_int(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: *mut i64,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn meta_data_get_unsigned_int(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: *mut u64,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn meta_data_get_double(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: *mut f64,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn meta_data_get_boolean(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: *mut bool,
+    ) -> ::std::os::raw::c_int;
+}
 pub type counter_t = ::std::os::raw::c_ulonglong;
 pub type gauge_t = f64;
 pub type derive_t = i64;
@@ -22,11 +22,627 @@ extern "C" {
 pub mod overrides {
     use super::*;
 
+    thread_local! {
+        static DISPATCHED: ::std::cell::Cell<usize> = ::std::cell::Cell::default();
+    }
+
+    /// The number of value lists dispatched from the current thread so far
+    pub fn stub_dispatched() -> usize {
+        DISPATCHED.with(|x| x.get())
+    }
+
     #[no_mangle]
     pub extern "C" fn plugin_dispatch_values(vl: *const value_list_t) -> ::std::os::raw::c_int {
+        DISPATCHED.with(|x| x.set(x.get() + 1));
+        0
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_dispatch_missing(vl: *const value_list_t) -> ::std::os::raw::c_int {
+        0
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_get_interval() -> cdtime_t {
+        0
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_dispatch_notification(
+        notif: *const notification_t,
+    ) -> ::std::os::raw::c_int {
+        0
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_notification_meta_add_string(
+        n: *mut notification_t,
+        name: *const ::std::os::raw::c_char,
+        value: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int {
+        0
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_notification_meta_add_signed_int(
+        n: *mut notification_t,
+        name: *const ::std::os::raw::c_char,
+        value: i64,
+    ) -> ::std::os::raw::c_int {
+        0
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_notification_meta_add_unsigned_int(
+        n: *mut notification_t,
+        name: *const ::std::os::raw::c_char,
+        value: u64,
+    ) -> ::std::os::raw::c_int {
+        0
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_notification_meta_add_double(
+        n: *mut notification_t,
+        name: *const ::std::os::raw::c_char,
+        value: f64,
+    ) -> ::std::os::raw::c_int {
         0
     }
 
+    #[no_mangle]
+    pub extern "C" fn plugin_notification_meta_add_boolean(
+        n: *mut notification_t,
+        name: *const ::std::os::raw::c_char,
+        value: bool,
+    ) -> ::std::os::raw::c_int {
+        0
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_notification_meta_free(
+        n: *mut notification_meta_t,
+    ) -> ::std::os::raw::c_int {
+        0
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_unregister_read(
+        name: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int {
+        stub_register("unregister read", name)
+    }
+
+    lazy_static! {
+        static ref REGISTRATIONS: ::std::sync::Mutex<Vec<(&'static str, String)>> =
+            ::std::sync::Mutex::new(Vec::new());
+    }
+
+    fn stub_register(
+        kind: &'static str,
+        name: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int {
+        let name = unsafe { ::std::ffi::CStr::from_ptr(name) };
+        let mut registrations = REGISTRATIONS.lock().unwrap();
+        registrations.push((kind, name.to_string_lossy().into_owned()));
+        0
+    }
+
+    /// The kind (eg: "config", "init", or "read") and name of each callback registered so far
+    pub fn stub_registrations() -> Vec<(&'static str, String)> {
+        REGISTRATIONS.lock().unwrap().clone()
+    }
+
+    lazy_static! {
+        static ref INIT_CALLBACKS: ::std::sync::Mutex<Vec<(String, plugin_init_cb)>> =
+            ::std::sync::Mutex::new(Vec::new());
+    }
+
+    fn stub_register_init(
+        name: *const ::std::os::raw::c_char,
+        callback: plugin_init_cb,
+    ) -> ::std::os::raw::c_int {
+        let key = unsafe { ::std::ffi::CStr::from_ptr(name) };
+        let mut callbacks = INIT_CALLBACKS.lock().unwrap();
+        callbacks.push((key.to_string_lossy().into_owned(), callback));
+        stub_register("init", name)
+    }
+
+    /// Invokes the init callback registered under the given name, as collectd would on startup
+    pub fn stub_init(name: &str) -> ::std::os::raw::c_int {
+        let callback = INIT_CALLBACKS
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|x| x.0 == name)
+            .and_then(|x| x.1)
+            .expect("init callback to be registered");
+
+        unsafe { callback() }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_register_config(
+        name: *const ::std::os::raw::c_char,
+        callback: ::std::option::Option<
+            unsafe extern "C" fn(
+                key: *const ::std::os::raw::c_char,
+                val: *const ::std::os::raw::c_char,
+            ) -> ::std::os::raw::c_int,
+        >,
+        keys: *mut *const ::std::os::raw::c_char,
+        keys_num: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int {
+        stub_register("config", name)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_register_complex_config(
+        type_: *const ::std::os::raw::c_char,
+        callback: ::std::option::Option<
+            unsafe extern "C" fn(arg1: *mut oconfig_item_t) -> ::std::os::raw::c_int,
+        >,
+    ) -> ::std::os::raw::c_int {
+        stub_register("config", type_)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_register_init(
+        name: *const ::std::os::raw::c_char,
+        callback: plugin_init_cb,
+    ) -> ::std::os::raw::c_int {
+        stub_register_init(name, callback)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_register_shutdown(
+        name: *const ::std::os::raw::c_char,
+        callback: plugin_shutdown_cb,
+    ) -> ::std::os::raw::c_int {
+        stub_register("shutdown", name)
+    }
+
+    // Collectd's plugin_log is variadic, which can't be defined in Rust. A stub that ignores its
+    // arguments only needs the symbol, so the variadic arguments are left out
+    #[no_mangle]
+    pub extern "C" fn plugin_log(
+        level: ::std::os::raw::c_int,
+        format: *const ::std::os::raw::c_char,
+    ) {
+    }
+
+    #[cfg(collectd57)]
+    #[no_mangle]
+    pub extern "C" fn plugin_register_complex_read(
+        group: *const ::std::os::raw::c_char,
+        name: *const ::std::os::raw::c_char,
+        callback: plugin_read_cb,
+        interval: cdtime_t,
+        user_data: *const user_data_t,
+    ) -> ::std::os::raw::c_int {
+        stub_register("read", name)
+    }
+
+    #[cfg(not(collectd57))]
+    #[no_mangle]
+    pub extern "C" fn plugin_register_complex_read(
+        group: *const ::std::os::raw::c_char,
+        name: *const ::std::os::raw::c_char,
+        callback: plugin_read_cb,
+        interval: *const timespec,
+        user_data: *mut user_data_t,
+    ) -> ::std::os::raw::c_int {
+        stub_register("read", name)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_register_write(
+        name: *const ::std::os::raw::c_char,
+        callback: plugin_write_cb,
+        user_data: *const user_data_t,
+    ) -> ::std::os::raw::c_int {
+        stub_register("write", name)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_register_flush(
+        name: *const ::std::os::raw::c_char,
+        callback: plugin_flush_cb,
+        user_data: *const user_data_t,
+    ) -> ::std::os::raw::c_int {
+        stub_register("flush", name)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_register_missing(
+        name: *const ::std::os::raw::c_char,
+        callback: plugin_missing_cb,
+        user_data: *const user_data_t,
+    ) -> ::std::os::raw::c_int {
+        stub_register("missing", name)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_register_log(
+        name: *const ::std::os::raw::c_char,
+        callback: plugin_log_cb,
+        user_data: *const user_data_t,
+    ) -> ::std::os::raw::c_int {
+        stub_register("log", name)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_register_notification(
+        name: *const ::std::os::raw::c_char,
+        callback: plugin_notification_cb,
+        user_data: *const user_data_t,
+    ) -> ::std::os::raw::c_int {
+        stub_register("notification", name)
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_register_data_set(ds: *const data_set_t) -> ::std::os::raw::c_int {
+        0
+    }
+
+    extern "C" {
+        fn malloc(size: usize) -> *mut ::std::os::raw::c_void;
+        fn free(ptr: *mut ::std::os::raw::c_void);
+        fn strdup(s: *const ::std::os::raw::c_char) -> *mut ::std::os::raw::c_char;
+    }
+
+    #[derive(Clone, Copy)]
+    enum StubMetaValue {
+        String(*mut ::std::os::raw::c_char),
+        SignedInt(i64),
+        UnsignedInt(u64),
+        Double(f64),
+        Boolean(bool),
+    }
+
+    type StubMetaData = Vec<(::std::ffi::CString, StubMetaValue)>;
+
+    fn stub_meta<'a>(md: *mut meta_data_t) -> &'a mut StubMetaData {
+        unsafe { &mut *(md as *mut StubMetaData) }
+    }
+
+    fn stub_meta_get(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+    ) -> Option<StubMetaValue> {
+        let key = unsafe { ::std::ffi::CStr::from_ptr(key) };
+        stub_meta(md)
+            .iter()
+            .find(|x| x.0.as_bytes().eq_ignore_ascii_case(key.to_bytes()))
+            .map(|x| x.1)
+    }
+
+    fn stub_meta_add(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: StubMetaValue,
+    ) -> ::std::os::raw::c_int {
+        let key = unsafe { ::std::ffi::CStr::from_ptr(key) }.to_owned();
+        let entries = stub_meta(md);
+        entries.retain(|x| !x.0.as_bytes().eq_ignore_ascii_case(key.as_bytes()));
+        entries.push((key, value));
+        0
+    }
+
+    fn stub_write<T>(dest: *mut T, value: T) -> ::std::os::raw::c_int {
+        unsafe { *dest = value };
+        0
+    }
+
+    // Strings given out are allocated with malloc, as the caller releases them with free
+    fn stub_strdup(s: *const ::std::os::raw::c_char) -> *mut ::std::os::raw::c_char {
+        unsafe { strdup(s) }
+    }
+
+    fn stub_strdup_all(strs: &[*const ::std::os::raw::c_char]) -> *mut *mut ::std::os::raw::c_char {
+        let size = strs.len() * ::std::mem::size_of::<*mut ::std::os::raw::c_char>();
+        unsafe {
+            let result = malloc(size) as *mut *mut ::std::os::raw::c_char;
+            let dests = ::std::slice::from_raw_parts_mut(result, strs.len());
+            for (dest, &s) in dests.iter_mut().zip(strs) {
+                *dest = strdup(s);
+            }
+            result
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_create() -> *mut meta_data_t {
+        Box::into_raw(Box::new(StubMetaData::new())) as *mut meta_data_t
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_destroy(md: *mut meta_data_t) {
+        let entries = unsafe { Box::from_raw(md as *mut StubMetaData) };
+        for &(_, value) in entries.iter() {
+            if let StubMetaValue::String(x) = value {
+                unsafe { free(x as *mut ::std::os::raw::c_void) };
+            }
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_exists(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int {
+        stub_meta_get(md, key).is_some() as ::std::os::raw::c_int
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_type(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int {
+        let kind = match stub_meta_get(md, key) {
+            Some(StubMetaValue::String(_)) => MD_TYPE_STRING,
+            Some(StubMetaValue::SignedInt(_)) => MD_TYPE_SIGNED_INT,
+            Some(StubMetaValue::UnsignedInt(_)) => MD_TYPE_UNSIGNED_INT,
+            Some(StubMetaValue::Double(_)) => MD_TYPE_DOUBLE,
+            Some(StubMetaValue::Boolean(_)) => MD_TYPE_BOOLEAN,
+            None => 0,
+        };
+        kind as ::std::os::raw::c_int
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_toc(
+        md: *mut meta_data_t,
+        toc: *mut *mut *mut ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int {
+        let entries = stub_meta(md);
+        let keys: Vec<*const ::std::os::raw::c_char> =
+            entries.iter().map(|x| x.0.as_ptr()).collect();
+        stub_write(toc, stub_strdup_all(&keys));
+        entries.len() as ::std::os::raw::c_int
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_add_string(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int {
+        stub_meta_add(md, key, StubMetaValue::String(stub_strdup(value)))
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_add_signed_int(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: i64,
+    ) -> ::std::os::raw::c_int {
+        stub_meta_add(md, key, StubMetaValue::SignedInt(value))
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_add_unsigned_int(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: u64,
+    ) -> ::std::os::raw::c_int {
+        stub_meta_add(md, key, StubMetaValue::UnsignedInt(value))
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_add_double(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: f64,
+    ) -> ::std::os::raw::c_int {
+        stub_meta_add(md, key, StubMetaValue::Double(value))
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_add_boolean(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: bool,
+    ) -> ::std::os::raw::c_int {
+        stub_meta_add(md, key, StubMetaValue::Boolean(value))
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_get_string(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: *mut *mut ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int {
+        match stub_meta_get(md, key) {
+            Some(StubMetaValue::String(x)) => stub_write(value, stub_strdup(x)),
+            _ => -1,
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_get_signed_int(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: *mut i64,
+    ) -> ::std::os::raw::c_int {
+        match stub_meta_get(md, key) {
+            Some(StubMetaValue::SignedInt(x)) => stub_write(value, x),
+            _ => -1,
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_get_unsigned_int(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: *mut u64,
+    ) -> ::std::os::raw::c_int {
+        match stub_meta_get(md, key) {
+            Some(StubMetaValue::UnsignedInt(x)) => stub_write(value, x),
+            _ => -1,
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_get_double(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: *mut f64,
+    ) -> ::std::os::raw::c_int {
+        match stub_meta_get(md, key) {
+            Some(StubMetaValue::Double(x)) => stub_write(value, x),
+            _ => -1,
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn meta_data_get_boolean(
+        md: *mut meta_data_t,
+        key: *const ::std::os::raw::c_char,
+        value: *mut bool,
+    ) -> ::std::os::raw::c_int {
+        match stub_meta_get(md, key) {
+            Some(StubMetaValue::Boolean(x)) => stub_write(value, x),
+            _ => -1,
+        }
+    }
+
+    fn empty_ctx() -> plugin_ctx_t {
+        unsafe { ::std::mem::zeroed() }
+    }
+
+    thread_local! {
+        static PLUGIN_CTX: ::std::cell::Cell<plugin_ctx_t> = ::std::cell::Cell::new(empty_ctx());
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_get_ctx() -> plugin_ctx_t {
+        PLUGIN_CTX.with(|x| x.get())
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_set_ctx(ctx: plugin_ctx_t) -> plugin_ctx_t {
+        PLUGIN_CTX.with(|x| x.replace(ctx))
+    }
+
+    // Threads are started with std, which creates pthreads underneath, so the thread can still be
+    // joined or detached through the returned pthread_t
+    fn spawn_stub_thread(
+        thread: *mut pthread_t,
+        start_routine: ::std::option::Option<
+            unsafe extern "C" fn(arg1: *mut ::std::os::raw::c_void) -> *mut ::std::os::raw::c_void,
+        >,
+        arg: *mut ::std::os::raw::c_void,
+    ) -> ::std::os::raw::c_int {
+        use std::os::unix::thread::JoinHandleExt;
+
+        let start = start_routine.expect("thread start routine");
+        let arg = arg as usize;
+        let handle = ::std::thread::spawn(move || unsafe {
+            start(arg as *mut ::std::os::raw::c_void);
+        });
+
+        unsafe {
+            *thread = handle.into_pthread_t();
+        }
+        0
+    }
+
+    #[cfg(collectd57)]
+    #[no_mangle]
+    pub extern "C" fn plugin_thread_create(
+        thread: *mut pthread_t,
+        attr: *const pthread_attr_t,
+        start_routine: ::std::option::Option<
+            unsafe extern "C" fn(arg1: *mut ::std::os::raw::c_void) -> *mut ::std::os::raw::c_void,
+        >,
+        arg: *mut ::std::os::raw::c_void,
+        name: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int {
+        spawn_stub_thread(thread, start_routine, arg)
+    }
+
+    #[cfg(not(collectd57))]
+    #[no_mangle]
+    pub extern "C" fn plugin_thread_create(
+        thread: *mut pthread_t,
+        attr: *const pthread_attr_t,
+        start_routine: ::std::option::Option<
+            unsafe extern "C" fn(arg1: *mut ::std::os::raw::c_void) -> *mut ::std::os::raw::c_void,
+        >,
+        arg: *mut ::std::os::raw::c_void,
+    ) -> ::std::os::raw::c_int {
+        spawn_stub_thread(thread, start_routine, arg)
+    }
+
+    type StubDataSet = (data_set_t, Vec<data_source_t>);
+
+    // A few of the types from collectd's types.db, for tests that submit values
+    fn stub_data_sets() -> Vec<StubDataSet> {
+        let gauge = DS_TYPE_GAUGE as ::std::os::raw::c_int;
+        let derive = DS_TYPE_DERIVE as ::std::os::raw::c_int;
+        vec![
+            stub_data_set(
+                "load",
+                &[
+                    ("shortterm", gauge),
+                    ("midterm", gauge),
+                    ("longterm", gauge),
+                ],
+            ),
+            stub_data_set("disk_octets", &[("read", derive), ("write", derive)]),
+            stub_data_set("memory", &[("value", gauge)]),
+            stub_data_set("percent", &[("value", gauge)]),
+        ]
+    }
+
+    fn stub_data_set(type_: &str, sources: &[(&str, ::std::os::raw::c_int)]) -> StubDataSet {
+        use std::f64;
+        let mut sources: Vec<data_source_t> = sources
+            .iter()
+            .map(|&(name, kind)| data_source_t {
+                name: stub_array(name),
+                type_: kind,
+                min: 0.0,
+                max: f64::NAN,
+            })
+            .collect();
+
+        let set = data_set_t {
+            type_: stub_array(type_),
+            ds_num: sources.len() as _,
+            ds: sources.as_mut_ptr(),
+        };
+        (set, sources)
+    }
+
+    fn stub_array(s: &str) -> [::std::os::raw::c_char; ARR_LENGTH] {
+        let mut arr = [0; ARR_LENGTH];
+        for (dest, &b) in arr.iter_mut().zip(s.as_bytes()) {
+            *dest = b as ::std::os::raw::c_char;
+        }
+        arr
+    }
+
+    thread_local! {
+        static DATA_SETS: Vec<StubDataSet> = stub_data_sets();
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_get_ds(name: *const ::std::os::raw::c_char) -> *const data_set_t {
+        stub_get_ds(name)
+    }
+
+    fn stub_get_ds(name: *const ::std::os::raw::c_char) -> *const data_set_t {
+        let name = unsafe { ::std::ffi::CStr::from_ptr(name) };
+        DATA_SETS.with(|sets| {
+            sets.iter()
+                .find(|x| unsafe { ::std::ffi::CStr::from_ptr(x.0.type_.as_ptr()) } == name)
+                .map(|x| &x.0 as *const data_set_t)
+                .unwrap_or(::std::ptr::null())
+        })
+    }
+
     #[no_mangle]
     pub static mut hostname_g: [::std::os::raw::c_char; ARR_LENGTH] = [0; ARR_LENGTH];
 }
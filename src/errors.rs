@@ -1,3 +1,6 @@
+use api::ValueType;
+use failure::{Context, Error};
+
 /// Errors that occur when converting Rust's text data to a format collectd expects
 #[derive(Fail, Debug)]
 pub enum ArrayError {
@@ -17,6 +20,105 @@ pub enum SubmitError {
     /// Contains the exit status that collectd returns when a submission fails
     #[fail(display = "plugin_dispatch_values returned an error: {}", _0)]
     DispatchError(i32),
+
+    /// Contains the exit status that collectd returns when declaring values missing fails
+    #[fail(display = "plugin_dispatch_missing returned an error: {}", _0)]
+    MissingError(i32),
+
+    /// Contains the exit status that collectd returns when dispatching a notification fails. This
+    /// also happens when no plugin has registered to receive notifications.
+    #[fail(display = "plugin_dispatch_notification returned an error: {}", _0)]
+    NotificationError(i32),
+
+    /// Collectd could not attach the named meta data entry to a notification
+    #[fail(display = "unable to add notification meta: {}", _0)]
+    NotificationMetaError(String),
+
+    /// Collectd could not attach the named meta data entry to a value list
+    #[fail(display = "unable to add value list meta: {}", _0)]
+    ValueMetaError(String),
+
+    /// Only gauges can be stored as a percentage. Contains the type instance of the offending value
+    #[fail(display = "only gauges can be stored as a percentage: {}", _0)]
+    PercentageError(String),
+
+    /// Values were submitted under a type that is neither in types.db nor a registered data set
+    #[fail(
+        display = "{} submitted values of type {}, which is not in types.db or a registered data set",
+        plugin, type_
+    )]
+    UnknownTypeError { plugin: String, type_: String },
+
+    /// The number of values submitted differs from the number of data sources of the type
+    #[fail(
+        display = "{} submitted {} values of type {}, which has {} data sources",
+        plugin, actual, type_, expected
+    )]
+    ValueCountError {
+        plugin: String,
+        type_: String,
+        expected: usize,
+        actual: usize,
+    },
+
+    /// A value submitted is of a different kind (eg: a gauge) than its data source (eg: a derive)
+    #[fail(
+        display = "{} submitted a {:?} for data source {} of type {}, which is a {:?}",
+        plugin, actual, name, type_, expected
+    )]
+    ValueTypeError {
+        plugin: String,
+        type_: String,
+        name: String,
+        expected: ValueType,
+        actual: ValueType,
+    },
+}
+
+/// Errors that occur when registering definitions with collectd
+#[derive(Fail, Debug)]
+pub enum RegistrationError {
+    /// Contains the exit status that collectd returns when registering a data set fails
+    #[fail(display = "plugin_register_data_set returned an error: {}", _0)]
+    DataSetError(i32),
+}
+
+/// Controls how collectd reacts to a failed read. By default, an error returned from
+/// `Plugin::read_values` causes collectd to back off exponentially from reading the plugin, until
+/// a delay of a day is reached. Return one of these instead, either directly
+/// (`Err(ReadError::Skip.into())`) or as the context of another error
+/// (`Err(e.context(ReadError::Transient).into())`), to change the reaction.
+#[derive(Fail, Debug, Clone, Copy, PartialEq)]
+pub enum ReadError {
+    /// The error is logged and the plugin is read again at the next interval, without backing off
+    #[fail(display = "transient read failure")]
+    Transient,
+
+    /// There is nothing to report this interval. Nothing is logged and the plugin is read again at
+    /// the next interval.
+    #[fail(display = "read skipped")]
+    Skip,
+
+    /// The plugin can't recover. The error is logged, the plugin's read callback is unregistered,
+    /// and its other callbacks are disabled.
+    #[fail(display = "permanent read failure")]
+    Permanent,
+}
+
+impl ReadError {
+    /// Returns the `ReadError` that an error is, or has as its context, looking through the whole
+    /// chain of causes (eg: when more context was added on top of a `ReadError`)
+    pub fn of(err: &Error) -> Option<ReadError> {
+        err.causes()
+            .filter_map(|cause| {
+                cause.downcast_ref::<ReadError>().cloned().or_else(|| {
+                    cause
+                        .downcast_ref::<Context<ReadError>>()
+                        .map(|x| *x.get_context())
+                })
+            })
+            .next()
+    }
 }
 
 /// If a plugin advertises that it supports a certain functionality, but doesn't implement the
@@ -24,3 +126,31 @@ pub enum SubmitError {
 #[derive(Fail, Debug)]
 #[fail(display = "Function is not implemented")]
 pub struct NotImplemented;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::Fail;
+
+    #[test]
+    fn test_read_error_of() {
+        let err = Error::from(ReadError::Skip);
+        assert_eq!(ReadError::of(&err), Some(ReadError::Skip));
+
+        let err = Error::from(NotImplemented.context(ReadError::Transient));
+        assert_eq!(ReadError::of(&err), Some(ReadError::Transient));
+
+        let err = Error::from(
+            NotImplemented
+                .context(ReadError::Permanent)
+                .context("unable to read disk"),
+        );
+        assert_eq!(ReadError::of(&err), Some(ReadError::Permanent));
+
+        let err = Error::from(ReadError::Skip).context("nothing to report");
+        assert_eq!(ReadError::of(&Error::from(err)), Some(ReadError::Skip));
+
+        let err = Error::from(NotImplemented);
+        assert_eq!(ReadError::of(&err), None);
+    }
+}
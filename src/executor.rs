@@ -0,0 +1,323 @@
+#[cfg(feature = "async")]
+use api::{PluginContext, ValueList};
+#[cfg(feature = "async")]
+use errors::NotImplemented;
+#[cfg(feature = "async")]
+use failure::Error;
+#[cfg(feature = "async")]
+use futures::{future, Future, IntoFuture, Poll};
+#[cfg(feature = "async")]
+use futures_cpupool::{Builder, CpuFuture, CpuPool};
+#[cfg(feature = "async")]
+use plugins::{Plugin, PluginCapabilities};
+#[cfg(feature = "async")]
+use std::sync::Mutex;
+
+/// A future returned from the callbacks of an `AsyncPlugin`
+#[cfg(feature = "async")]
+pub type PluginFuture<'a> = Box<Future<Item = (), Error = Error> + 'a>;
+
+/// A plugin whose reads and writes are futures (requires the `async` feature). Each callback is
+/// given the executor, a thread pool that collectd-plugin starts when collectd initializes the
+/// plugin and stops when collectd shuts down, so that blocking work (eg: requests to HTTP or
+/// database endpoints) can be run concurrently. Work spawned on the executor runs in the plugin
+/// context of the callback, so values it dispatches get the plugin's defaults (eg: `Interval`). Collectd's callback waits on the returned future,
+/// so a read is finished once its future completes. Register an `AsyncPlugin` by converting it
+/// into a `Plugin` with `async_plugin`.
+#[cfg(feature = "async")]
+pub trait AsyncPlugin: Send + Sync {
+    /// Same as `Plugin::capabilities`, though only `READ` and `WRITE` are supported
+    fn capabilities(&self) -> PluginCapabilities {
+        PluginCapabilities::default()
+    }
+
+    /// Same as `Plugin::read_values`
+    fn read_values<'a>(&'a self, _executor: &PluginExecutor) -> PluginFuture<'a> {
+        Box::new(future::err(Error::from(NotImplemented)))
+    }
+
+    /// Same as `Plugin::write_values`
+    fn write_values<'a>(
+        &'a self,
+        _executor: &PluginExecutor,
+        _list: ValueList<'a>,
+    ) -> PluginFuture<'a> {
+        Box::new(future::err(Error::from(NotImplemented)))
+    }
+}
+
+/// Converts an `AsyncPlugin` into a `Plugin`, which is ready to be returned in a
+/// `PluginRegistration`. Fails if the plugin has capabilities other than `READ` and `WRITE`.
+#[cfg(feature = "async")]
+pub fn async_plugin<T: AsyncPlugin + 'static>(plugin: T) -> Result<Box<Plugin>, Error> {
+    let unsupported =
+        plugin.capabilities() - (PluginCapabilities::READ | PluginCapabilities::WRITE);
+    if !unsupported.is_empty() {
+        return Err(format_err!(
+            "asynchronous plugins only support READ and WRITE, not {:?}",
+            unsupported
+        ));
+    }
+
+    Ok(Box::new(AsyncAdapter(plugin)))
+}
+
+#[cfg(feature = "async")]
+struct AsyncAdapter<T>(T);
+
+#[cfg(feature = "async")]
+impl<T: AsyncPlugin> Plugin for AsyncAdapter<T> {
+    fn capabilities(&self) -> PluginCapabilities {
+        self.0.capabilities()
+    }
+
+    fn read_values(&self) -> Result<(), Error> {
+        self.0.read_values(&running_executor()?).wait()
+    }
+
+    fn write_values<'a>(&self, list: ValueList<'a>) -> Result<(), Error> {
+        self.0.write_values(&running_executor()?, list).wait()
+    }
+}
+
+#[cfg(feature = "async")]
+fn running_executor() -> Result<PluginExecutor, Error> {
+    executor().ok_or_else(|| format_err!("the executor of asynchronous plugins is not running"))
+}
+
+/// The executor of asynchronous plugins, as seen from one of collectd's callbacks. Work spawned
+/// through it runs on the shared thread pool in the plugin context of the thread that the
+/// executor was obtained on, as the pool's threads are started outside of collectd and are shared
+/// by every plugin in the library.
+#[cfg(feature = "async")]
+#[derive(Clone)]
+pub struct PluginExecutor {
+    pool: CpuPool,
+    ctx: PluginContext,
+}
+
+#[cfg(feature = "async")]
+impl PluginExecutor {
+    /// Spawns a future onto the thread pool, which is polled in the captured plugin context
+    pub fn spawn<F>(&self, f: F) -> CpuFuture<F::Item, F::Error>
+    where
+        F: Future + Send + 'static,
+        F::Item: Send + 'static,
+        F::Error: Send + 'static,
+    {
+        self.pool.spawn(InContext {
+            ctx: self.ctx,
+            future: f,
+        })
+    }
+
+    /// Spawns a closure onto the thread pool, which is run in the captured plugin context
+    pub fn spawn_fn<F, R>(&self, f: F) -> CpuFuture<R::Item, R::Error>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: IntoFuture + 'static,
+        R::Future: Send + 'static,
+        R::Item: Send + 'static,
+        R::Error: Send + 'static,
+    {
+        self.spawn(future::lazy(f))
+    }
+
+    /// The underlying thread pool. Work spawned directly on the pool runs outside of any plugin
+    /// context.
+    pub fn pool(&self) -> &CpuPool {
+        &self.pool
+    }
+}
+
+// Sets the plugin context of the thread while the future is polled, and restores the previous one
+// afterwards, as the thread goes on to run work of other plugins
+#[cfg(feature = "async")]
+struct InContext<F> {
+    ctx: PluginContext,
+    future: F,
+}
+
+#[cfg(feature = "async")]
+impl<F: Future> Future for InContext<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let previous = self.ctx.set();
+        let result = self.future.poll();
+        previous.set();
+        result
+    }
+}
+
+// The executor is shared by all plugin managers in the library, each of which starts it on init
+// and stops it on shutdown, so it is only stopped once the last manager has shut down
+#[cfg(feature = "async")]
+struct Executor {
+    pool: Option<CpuPool>,
+    users: usize,
+}
+
+#[cfg(feature = "async")]
+lazy_static! {
+    static ref EXECUTOR: Mutex<Executor> = Mutex::new(Executor {
+        pool: None,
+        users: 0,
+    });
+}
+
+/// Returns the executor shared by all asynchronous plugins in the library, which is running from
+/// when collectd initializes the plugins until collectd shuts them down. Work spawned through the
+/// returned `PluginExecutor` runs in the plugin context of the calling thread.
+#[cfg(feature = "async")]
+pub fn executor() -> Option<PluginExecutor> {
+    let pool = EXECUTOR.lock().unwrap().pool.clone();
+    pool.map(|pool| PluginExecutor {
+        pool,
+        ctx: PluginContext::current(),
+    })
+}
+
+#[cfg(feature = "async")]
+fn create_pool() -> CpuPool {
+    Builder::new().name_prefix("collectd-rust-").create()
+}
+
+/// Starts the executor of asynchronous plugins (when the `async` feature is enabled), or adds a
+/// user to the executor if it is already running
+#[doc(hidden)]
+#[cfg(feature = "async")]
+pub fn start_executor() {
+    let mut executor = EXECUTOR.lock().unwrap();
+    executor.users += 1;
+    if executor.pool.is_none() {
+        executor.pool = Some(create_pool());
+    }
+}
+
+#[doc(hidden)]
+#[cfg(not(feature = "async"))]
+pub fn start_executor() {}
+
+/// Removes a user of the executor of asynchronous plugins, and stops the executor once it has no
+/// users left. Work that has already been spawned on the executor runs to completion, after which
+/// its threads exit.
+#[doc(hidden)]
+#[cfg(feature = "async")]
+pub fn stop_executor() {
+    let mut executor = EXECUTOR.lock().unwrap();
+    executor.users = executor.users.saturating_sub(1);
+    if executor.users == 0 {
+        executor.pool.take();
+    }
+}
+
+#[doc(hidden)]
+#[cfg(not(feature = "async"))]
+pub fn stop_executor() {}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_async_plugin() {
+        struct Counter {
+            reads: Arc<AtomicUsize>,
+        }
+
+        impl AsyncPlugin for Counter {
+            fn capabilities(&self) -> PluginCapabilities {
+                PluginCapabilities::READ
+            }
+
+            fn read_values<'a>(&'a self, executor: &PluginExecutor) -> PluginFuture<'a> {
+                let reads = self.reads.clone();
+                let a = executor.spawn_fn(move || -> Result<(), Error> {
+                    reads.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                });
+                let b = executor.spawn_fn(|| -> Result<(), Error> { Ok(()) });
+                Box::new(a.join(b).map(|_| ()))
+            }
+        }
+
+        start_executor();
+        let reads = Arc::new(AtomicUsize::new(0));
+        let plugin = async_plugin(Counter {
+            reads: reads.clone(),
+        })
+        .unwrap();
+        assert_eq!(plugin.capabilities(), PluginCapabilities::READ);
+        assert!(plugin.read_values().is_ok());
+        assert!(plugin.read_values().is_ok());
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+        stop_executor();
+    }
+
+    #[test]
+    fn test_shared_executor() {
+        // Two managers start the executor, and the first to shut down leaves it running
+        start_executor();
+        start_executor();
+        stop_executor();
+        assert!(executor().is_some());
+        stop_executor();
+    }
+
+    #[test]
+    fn test_async_plugin_capabilities() {
+        struct Logger;
+
+        impl AsyncPlugin for Logger {
+            fn capabilities(&self) -> PluginCapabilities {
+                PluginCapabilities::READ | PluginCapabilities::LOG
+            }
+        }
+
+        assert!(async_plugin(Logger).is_err());
+    }
+
+    #[test]
+    fn test_executor_plugin_context() {
+        use bindings::{plugin_get_ctx, plugin_set_ctx};
+        use chrono::Duration;
+
+        // Each callback may run in the context of a different plugin (eg: with its own interval),
+        // which carries over to the work that it spawns, but not to other work on the same pool
+        let with_interval = |secs: u64| unsafe {
+            let mut ctx = plugin_get_ctx();
+            ctx.interval = secs << 30;
+            plugin_set_ctx(ctx);
+        };
+
+        let interval = || -> Result<Duration, Error> { Ok(PluginContext::current().interval()) };
+
+        start_executor();
+        with_interval(10);
+        let ten = executor().unwrap();
+        with_interval(20);
+        let twenty = executor().unwrap();
+
+        assert_eq!(
+            ten.spawn_fn(interval).wait().unwrap(),
+            Duration::seconds(10)
+        );
+        assert_eq!(
+            twenty.spawn_fn(interval).wait().unwrap(),
+            Duration::seconds(20)
+        );
+        assert_eq!(
+            ten.spawn_fn(interval).wait().unwrap(),
+            Duration::seconds(10)
+        );
+        assert_eq!(
+            ten.pool().spawn_fn(interval).wait().unwrap(),
+            Duration::seconds(0)
+        );
+        stop_executor();
+    }
+}
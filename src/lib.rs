@@ -81,7 +81,7 @@
 //!         PluginCapabilities::READ
 //!     }
 //!
-//!     fn read_values(&mut self) -> Result<(), Error> {
+//!     fn read_values(&self) -> Result<(), Error> {
 //!         // Create a list of values to submit to collectd. We'll be sending in a vector representing the
 //!         // "load" type. Short-term load is first (15.0) followed by mid-term and long-term. The number
 //!         // of values that you submit at a time depends on types.db in collectd configurations
@@ -106,12 +106,19 @@ extern crate bitflags;
 extern crate chrono;
 #[macro_use]
 extern crate failure;
+#[macro_use]
+extern crate lazy_static;
 extern crate memchr;
 
 #[cfg(feature = "serde")]
 #[macro_use]
 extern crate serde;
 
+#[cfg(feature = "async")]
+extern crate futures;
+#[cfg(feature = "async")]
+extern crate futures_cpupool;
+
 #[cfg(test)]
 #[cfg(feature = "serde")]
 #[macro_use]
@@ -124,18 +131,50 @@ pub mod bindings;
 #[macro_use]
 mod api;
 mod errors;
+mod executor;
 #[macro_use]
 mod plugins;
 
 pub use api::{
-    collectd_log, empty_to_none, from_array, get_default_interval, nanos_to_collectd, CdTime,
-    ConfigItem, ConfigValue, LogLevel, Value, ValueList, ValueListBuilder, ValueReport,
+    collectd_log, empty_to_none, from_array, get_default_interval, nanos_to_collectd, spawn_thread,
+    CdTime, ConfigItem, ConfigValue, DataSet, DataSource, Identifier, LogLevel, MetaData,
+    MetaDataIter, MetaValue, MultiValueListBuilder, NameCache, Notification, NotificationBuilder,
+    NotificationLevel, NotificationMeta, OwnedConfigItem, OwnedConfigValue, OwnedMetaValue,
+    OwnedValueList, OwnedValueReport, PluginContext, PluginThread, Value, ValueList,
+    ValueListBuilder, ValueReport, ValueType,
 };
-pub use errors::{ArrayError, SubmitError};
+pub use errors::{ArrayError, ReadError, RegistrationError, SubmitError};
 pub use plugins::{
-    Plugin, PluginCapabilities, PluginManager, PluginManagerCapabilities, PluginRegistration,
+    FlushPlugin, InitPlugin, LogPlugin, MissingPlugin, NotificationPlugin, Plugin, PluginBuilder,
+    PluginCapabilities, PluginManager, PluginManagerCapabilities, PluginRegistration, ReadPlugin,
+    RegistrationHandle, ShutdownPlugin, StatefulManager, StatefulPluginManager, WritePlugin,
 };
 
+#[cfg(feature = "serde")]
+pub use plugins::TypedPluginManager;
+
+#[cfg(feature = "async")]
+pub use executor::{async_plugin, executor, AsyncPlugin, PluginExecutor, PluginFuture};
+#[cfg(feature = "async")]
+pub use futures_cpupool::{CpuFuture, CpuPool};
+
+#[doc(hidden)]
+pub use api::{flush_identifier, register_complex_read, register_config};
+#[doc(hidden)]
+pub use executor::{start_executor, stop_executor};
+#[doc(hidden)]
+pub use plugins::{
+    config_read_interval, merge_config, name_plugins, panic_message, stash_config,
+    stash_config_pair, take_config, take_config_pairs, track_plugin, tracked_plugins,
+    untrack_plugin, untrack_plugins, NamedPlugins, PluginEntry, RegisteredPlugin,
+};
+
+/// Types that the `collectd_plugin!` macro references, but downstream crates may not depend on
+#[doc(hidden)]
+pub mod export {
+    pub use chrono::Duration;
+}
+
 #[cfg(test)]
 #[allow(private_no_mangle_fns)]
 #[allow(dead_code)]
@@ -156,10 +195,89 @@ mod tests {
         }
     }
 
-    collectd_plugin!(MyPlugin);
+    struct MyOtherPlugin;
+
+    impl PluginManager for MyOtherPlugin {
+        fn name() -> &'static str {
+            "myotherplugin"
+        }
+
+        fn plugins(_config: Option<&[ConfigItem]>) -> Result<PluginRegistration, Error> {
+            Ok(PluginRegistration::Multiple(vec![]))
+        }
+    }
+
+    struct MyInitPlugin {
+        fail: bool,
+    }
+
+    impl Plugin for MyInitPlugin {
+        fn capabilities(&self) -> PluginCapabilities {
+            PluginCapabilities::READ | PluginCapabilities::INIT
+        }
+
+        fn initialize(&self) -> Result<(), Error> {
+            if self.fail {
+                Err(format_err!("unable to connect"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    struct MyInitManager;
+
+    impl PluginManager for MyInitManager {
+        fn name() -> &'static str {
+            "myinitplugin"
+        }
+
+        fn plugins(_config: Option<&[ConfigItem]>) -> Result<PluginRegistration, Error> {
+            Ok(PluginRegistration::Multiple(vec![
+                (String::from("good"), Box::new(MyInitPlugin { fail: false })),
+                (String::from("bad"), Box::new(MyInitPlugin { fail: true })),
+            ]))
+        }
+    }
+
+    collectd_plugin!(MyPlugin, MyOtherPlugin, MyInitManager);
 
     #[test]
     fn can_generate_blank_plugin() {
         assert!(true);
     }
+
+    #[test]
+    fn test_module_register() {
+        // Collectd calls module_register for every LoadPlugin line of the library, but each
+        // manager is only registered once
+        module_register();
+        module_register();
+
+        let registrations = bindings::overrides::stub_registrations();
+        for name in &["myplugin", "myotherplugin"] {
+            for kind in &["config", "init", "shutdown"] {
+                let count = registrations
+                    .iter()
+                    .filter(|x| x.0 == *kind && x.1 == *name)
+                    .count();
+                assert_eq!(count, 1, "{} {} registrations", name, kind);
+            }
+        }
+    }
+
+    #[test]
+    fn test_plugin_init_failure() {
+        // A plugin that fails to initialize never has its callbacks registered, while its
+        // siblings are registered as usual
+        module_register();
+        assert_eq!(bindings::overrides::stub_init("myinitplugin"), 0);
+
+        let reads: Vec<String> = bindings::overrides::stub_registrations()
+            .into_iter()
+            .filter(|x| x.0 == "read" && x.1.starts_with("myinitplugin"))
+            .map(|x| x.1)
+            .collect();
+        assert_eq!(reads, vec![String::from("myinitplugin/good")]);
+    }
 }
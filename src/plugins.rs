@@ -1,7 +1,22 @@
-use api::{ConfigItem, LogLevel, ValueList};
+use api::{
+    ConfigItem, ConfigValue, DataSet, Identifier, LogLevel, Notification, OwnedConfigItem,
+    OwnedConfigValue, ValueList,
+};
+use bindings::plugin_unregister_read;
 use chrono::Duration;
+#[cfg(feature = "serde")]
+use de::from_collectd;
 use errors::NotImplemented;
 use failure::Error;
+#[cfg(feature = "serde")]
+use failure::ResultExt;
+#[cfg(feature = "serde")]
+use serde::de::DeserializeOwned;
+use std::any::Any;
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 bitflags! {
     /// Bitflags of capabilities that a plugin advertises to collectd.
@@ -11,6 +26,10 @@ bitflags! {
         const LOG =    0b0000_0010;
         const WRITE =  0b0000_0100;
         const FLUSH =  0b0000_1000;
+        const NOTIFICATION = 0b0001_0000;
+        const SHUTDOWN = 0b0010_0000;
+        const MISSING = 0b0100_0000;
+        const INIT = 0b1000_0000;
     }
 }
 
@@ -19,6 +38,7 @@ bitflags! {
     #[derive(Default)]
     pub struct PluginManagerCapabilities: u32 {
         const INIT = 0b0000_0001;
+        const SHUTDOWN = 0b0000_0010;
     }
 }
 
@@ -47,6 +67,22 @@ impl PluginCapabilities {
     pub fn has_flush(self) -> bool {
         self.intersects(PluginCapabilities::FLUSH)
     }
+
+    pub fn has_notification(self) -> bool {
+        self.intersects(PluginCapabilities::NOTIFICATION)
+    }
+
+    pub fn has_shutdown(self) -> bool {
+        self.intersects(PluginCapabilities::SHUTDOWN)
+    }
+
+    pub fn has_missing(self) -> bool {
+        self.intersects(PluginCapabilities::MISSING)
+    }
+
+    pub fn has_init(self) -> bool {
+        self.intersects(PluginCapabilities::INIT)
+    }
 }
 
 /// Defines the entry point for a collectd plugin. Based on collectd's configuration, a
@@ -60,88 +96,1149 @@ pub trait PluginManager {
         PluginManagerCapabilities::default()
     }
 
+    /// Keys of collectd's simple config style, where each key in the plugin's `<Plugin>` block is
+    /// followed by a single value (as understood by plugins written against collectd's
+    /// `plugin_register_config`). If any keys are given, the plugin is configured in the simple
+    /// style: every key and value pair is passed to `plugins` as a `ConfigItem` with a single
+    /// string value, and keys not in the list are rejected by collectd. Useful when porting a C
+    /// plugin without changing its configuration.
+    fn config_keys() -> &'static [&'static str] {
+        &[]
+    }
+
     /// Returns one or many instances of a plugin that is configured from collectd's configuration
     /// file. If parameter is `None`, a configuration section for the plugin was not found, so
-    /// default values should be used.
+    /// default values should be used. Called once collectd has read all of its configuration, so
+    /// a plugin configured in several `<Plugin>` blocks (eg: one file per instance in a
+    /// `collectd.conf.d/` directory) receives the contents of all blocks merged together. An
+    /// `Interval` key, which sets the default read interval of the registered plugins, is passed
+    /// through as well, so configs that reject unknown keys should accept it.
     fn plugins(_config: Option<&[ConfigItem]>) -> Result<PluginRegistration, Error>;
 
+    /// Data sets (types) that the plugins submit values under, which collectd would otherwise need
+    /// to find in a types.db file. The data sets are registered when collectd initializes the
+    /// plugin, before any values are read, and replace any data set of the same name.
+    fn data_sets() -> Vec<DataSet<'static>> {
+        Vec::new()
+    }
+
     /// Initialize any socket, files, or expensive resources that may have been parsed from the
-    /// configuration. If an error is reported, all hooks registered will be unregistered. This is
-    /// really only useful for `PluginRegistration::Single` modules who want global data.
+    /// configuration. If an error is reported, collectd only unregisters the read callback named
+    /// after the manager (ie: that of a `PluginRegistration::Single` plugin), and all other
+    /// callbacks stay registered. This is really only useful for `PluginRegistration::Single`
+    /// modules who want global data. For resources that belong to a single plugin, see
+    /// `Plugin::initialize`.
+    fn initialize() -> Result<(), Error> {
+        Err(Error::from(NotImplemented))
+    }
+
+    /// Called when collectd is shutting down, after all registered plugins have been shutdown.
+    /// Release any global resources that were created in `initialize`. Requires a capability of
+    /// `SHUTDOWN`.
+    fn shutdown() -> Result<(), Error> {
+        Err(Error::from(NotImplemented))
+    }
+}
+
+/// A `PluginManager` whose configuration is deserialized by collectd-plugin (via serde), which
+/// removes the need to call `de::from_collectd` from `PluginManager::plugins`. Every
+/// `TypedPluginManager` is a `PluginManager`, so it is passed to `collectd_plugin!` as is. As that
+/// blanket implementation is what makes it a `PluginManager`, each of the `PluginManager` hooks
+/// has a counterpart here that it forwards to.
+#[cfg(feature = "serde")]
+pub trait TypedPluginManager {
+    /// The configuration of the plugin. If the plugin has no configuration section, the default
+    /// configuration is used. Like with `PluginManager::plugins`, it includes any `Interval` key.
+    type Config: DeserializeOwned + Default;
+
+    /// Name of the plugin.
+    fn name() -> &'static str;
+
+    /// Defines the capabilities of the plugin manager.
+    fn capabilities() -> PluginManagerCapabilities {
+        PluginManagerCapabilities::default()
+    }
+
+    /// Same as `PluginManager::config_keys`. Each key and value pair is deserialized as a field
+    /// holding a string.
+    fn config_keys() -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Same as `PluginManager::data_sets`
+    fn data_sets() -> Vec<DataSet<'static>> {
+        Vec::new()
+    }
+
+    /// Returns one or many instances of a plugin that is configured from the deserialized
+    /// configuration.
+    fn plugins_typed(config: Self::Config) -> Result<PluginRegistration, Error>;
+
+    /// Same as `PluginManager::initialize`
+    fn initialize() -> Result<(), Error> {
+        Err(Error::from(NotImplemented))
+    }
+
+    /// Same as `PluginManager::shutdown`
+    fn shutdown() -> Result<(), Error> {
+        Err(Error::from(NotImplemented))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: TypedPluginManager> PluginManager for T {
+    fn name() -> &'static str {
+        <T as TypedPluginManager>::name()
+    }
+
+    fn capabilities() -> PluginManagerCapabilities {
+        <T as TypedPluginManager>::capabilities()
+    }
+
+    fn config_keys() -> &'static [&'static str] {
+        <T as TypedPluginManager>::config_keys()
+    }
+
+    fn data_sets() -> Vec<DataSet<'static>> {
+        <T as TypedPluginManager>::data_sets()
+    }
+
+    fn plugins(config: Option<&[ConfigItem]>) -> Result<PluginRegistration, Error> {
+        let config = match config {
+            Some(config) => from_collectd(config).with_context(|_e| {
+                format!(
+                    "unable to deserialize config of {}",
+                    <T as TypedPluginManager>::name()
+                )
+            })?,
+            None => T::Config::default(),
+        };
+
+        T::plugins_typed(config)
+    }
+
     fn initialize() -> Result<(), Error> {
+        <T as TypedPluginManager>::initialize()
+    }
+
+    fn shutdown() -> Result<(), Error> {
+        <T as TypedPluginManager>::shutdown()
+    }
+}
+
+/// A plugin manager that is a value, constructed from the plugin's configuration, instead of a
+/// collection of associated functions. The manager is kept alive until collectd shuts down and
+/// each plugin it registers can hold onto a reference to it, so state shared between plugins
+/// (eg: a connection pool or rate limiter) doesn't need to live in globals. Pass the manager to
+/// `collectd_plugin!` wrapped in a `StatefulManager`:
+///
+/// ```rust,no_run
+/// #[macro_use]
+/// extern crate collectd_plugin;
+/// extern crate failure;
+///
+/// use collectd_plugin::{ConfigItem, Plugin, PluginCapabilities, PluginRegistration,
+///                       StatefulManager, StatefulPluginManager, Value, ValueListBuilder};
+/// use failure::Error;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+///
+/// // State shared by all of the manager's plugins
+/// #[derive(Default)]
+/// struct MyManager {
+///     reads: AtomicUsize,
+/// }
+///
+/// impl StatefulPluginManager for MyManager {
+///     fn name() -> &'static str {
+///         "myplugin"
+///     }
+///
+///     fn new(_config: Option<&[ConfigItem]>) -> Result<Self, Error> {
+///         Ok(MyManager::default())
+///     }
+///
+///     fn plugins(manager: &Arc<Self>) -> Result<PluginRegistration, Error> {
+///         let plugin = MyPlugin {
+///             manager: Arc::clone(manager),
+///         };
+///         Ok(PluginRegistration::Single(Box::new(plugin)))
+///     }
+/// }
+///
+/// struct MyPlugin {
+///     manager: Arc<MyManager>,
+/// }
+///
+/// impl Plugin for MyPlugin {
+///     fn capabilities(&self) -> PluginCapabilities {
+///         PluginCapabilities::READ
+///     }
+///
+///     fn read_values(&self) -> Result<(), Error> {
+///         let reads = self.manager.reads.fetch_add(1, Ordering::SeqCst) + 1;
+///         ValueListBuilder::new("myplugin", "count")
+///             .values(&[Value::Derive(reads as i64)])
+///             .submit()
+///     }
+/// }
+///
+/// collectd_plugin!(StatefulManager<MyManager>);
+///
+/// # fn main() {
+/// # }
+/// ```
+pub trait StatefulPluginManager: Send + Sync + Sized + 'static {
+    /// Name of the plugin.
+    fn name() -> &'static str;
+
+    /// Defines the capabilities of the plugin manager.
+    fn capabilities() -> PluginManagerCapabilities {
+        PluginManagerCapabilities::default()
+    }
+
+    /// Same as `PluginManager::config_keys`
+    fn config_keys() -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Same as `PluginManager::data_sets`
+    fn data_sets() -> Vec<DataSet<'static>> {
+        Vec::new()
+    }
+
+    /// Constructs the manager from collectd's configuration file. If parameter is `None`, a
+    /// configuration section for the plugin was not found, so default values should be used.
+    fn new(config: Option<&[ConfigItem]>) -> Result<Self, Error>;
+
+    /// Returns one or many instances of a plugin, which may clone the manager to share its state.
+    fn plugins(manager: &Arc<Self>) -> Result<PluginRegistration, Error>;
+
+    /// Same as `PluginManager::initialize`, called after the manager's plugins are created and
+    /// before they are initialized. Requires a capability of `INIT`.
+    fn initialize(&self) -> Result<(), Error> {
         Err(Error::from(NotImplemented))
     }
+
+    /// Same as `PluginManager::shutdown`, called after all registered plugins have been shutdown.
+    /// Requires a capability of `SHUTDOWN`.
+    fn shutdown(&self) -> Result<(), Error> {
+        Err(Error::from(NotImplemented))
+    }
+}
+
+/// Adapts a `StatefulPluginManager` into a `PluginManager` for `collectd_plugin!`
+pub struct StatefulManager<T>(PhantomData<T>);
+
+impl<T: StatefulPluginManager> StatefulManager<T> {
+    /// Returns the manager, if it has been constructed and collectd hasn't shut it down.
+    pub fn instance() -> Option<Arc<T>> {
+        let managers = MANAGERS.lock().unwrap();
+        managers
+            .iter()
+            .filter(|x| x.0 == T::name())
+            .filter_map(|x| x.1.downcast_ref::<Arc<T>>())
+            .next()
+            .cloned()
+    }
+}
+
+impl<T: StatefulPluginManager> PluginManager for StatefulManager<T> {
+    fn name() -> &'static str {
+        T::name()
+    }
+
+    fn capabilities() -> PluginManagerCapabilities {
+        T::capabilities()
+    }
+
+    fn config_keys() -> &'static [&'static str] {
+        T::config_keys()
+    }
+
+    fn data_sets() -> Vec<DataSet<'static>> {
+        T::data_sets()
+    }
+
+    fn plugins(config: Option<&[ConfigItem]>) -> Result<PluginRegistration, Error> {
+        let manager = Arc::new(T::new(config)?);
+        {
+            let mut managers = MANAGERS.lock().unwrap();
+            managers.retain(|x| x.0 != T::name());
+            managers.push((T::name(), Box::new(manager.clone())));
+        }
+
+        T::plugins(&manager)
+    }
+
+    fn initialize() -> Result<(), Error> {
+        match Self::instance() {
+            Some(manager) => manager.initialize(),
+            None => Err(format_err!("{} has not been constructed", T::name())),
+        }
+    }
+
+    fn shutdown() -> Result<(), Error> {
+        // Plugins may still hold onto the manager, so it is only dropped once they are freed
+        let manager = {
+            let mut managers = MANAGERS.lock().unwrap();
+            let (ours, theirs): (Vec<_>, Vec<_>) =
+                managers.drain(..).partition(|x| x.0 == T::name());
+            *managers = theirs;
+            ours.into_iter()
+                .filter_map(|x| x.1.downcast_ref::<Arc<T>>().cloned())
+                .next()
+        };
+
+        match manager {
+            Some(manager) => manager.shutdown(),
+            None => Ok(()),
+        }
+    }
+}
+
+lazy_static! {
+    static ref MANAGERS: Mutex<Vec<(&'static str, Box<Any + Send + Sync>)>> =
+        Mutex::new(Vec::new());
 }
 
 /// An individual plugin that is capable of reporting values to collectd, receiving values from
-/// other plugins, or logging messages. A plugin must implement `Sync` as collectd could be sending
-/// values to be written or logged concurrently. The Rust compiler will now ensure that everything
-/// not thread safe is wrapped in a Mutex (or another compatible datastructure)
-pub trait Plugin: Sync {
+/// other plugins, or logging messages. A plugin must implement `Send` and `Sync` as collectd could
+/// be sending values to be written or logged concurrently from different threads, which is why
+/// callbacks only receive `&self`. The Rust compiler will now ensure that everything not thread
+/// safe is wrapped in a Mutex (or another compatible datastructure)
+pub trait Plugin: Send + Sync {
     /// A plugin's capabilities. By default a plugin does nothing, but can advertise that it can
     /// configure itself and / or report values.
     fn capabilities(&self) -> PluginCapabilities {
         PluginCapabilities::default()
     }
 
+    /// Initialize any socket, files, or expensive resources the plugin needs. Called once, after
+    /// `PluginManager::initialize`, for every plugin with a capability of `INIT`, before any of
+    /// the plugin's callbacks are registered with collectd. If an error is reported, none of the
+    /// plugin's callbacks are registered and the plugin is dropped; other plugins from the same
+    /// manager continue to run.
+    fn initialize(&self) -> Result<(), Error> {
+        Err(Error::from(NotImplemented))
+    }
+
+    /// Called with a handle to the plugin's registration before any of its callbacks are
+    /// registered with collectd. Plugins that may need to unregister themselves at runtime (eg:
+    /// after detecting a permanent misconfiguration) should hold onto the handle.
+    fn registered(&mut self, _handle: RegistrationHandle) {}
+
     /// Customizes how a message of a given level is logged. If the message isn't valid UTF-8, an
     /// allocation is done to replace all invalid characters with the UTF-8 replacement character
-    fn log(&mut self, _lvl: LogLevel, _msg: &str) -> Result<(), Error> {
+    fn log(&self, _lvl: LogLevel, _msg: &str) -> Result<(), Error> {
         Err(Error::from(NotImplemented))
     }
 
+    /// The interval at which collectd will call `read_values` for this plugin. If `None`, the
+    /// `Interval` of the plugin's configuration block is used, else the global `Interval`. Useful
+    /// when some instances are more expensive to read than others.
+    fn read_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// The read group that this plugin's read callback is registered under, if any
+    fn read_group(&self) -> Option<&str> {
+        None
+    }
+
+    /// A panic in any of the plugin's callbacks is caught and logged, and the callback reports an
+    /// error to collectd. Once the plugin has panicked this many times, it is disabled: collectd
+    /// stops reading from the plugin and no other callbacks are passed through to it. If `None`,
+    /// the plugin is never disabled.
+    fn panic_limit(&self) -> Option<usize> {
+        None
+    }
+
     /// This function is called when collectd expects the plugin to report values, which will occur
     /// at the `Interval` defined in the global config (but can be overridden). Implementations
     /// that expect to report values need to have at least have a capability of `READ`. An error in
     /// reporting values will cause collectd to backoff exponentially until a delay of a day is
-    /// reached.
-    fn read_values(&mut self) -> Result<(), Error> {
+    /// reached, unless the error is a `ReadError` that says otherwise.
+    fn read_values(&self) -> Result<(), Error> {
         Err(Error::from(NotImplemented))
     }
 
     /// Collectd is giving you reported values, do with them as you please. If writing values is
     /// expensive, prefer to buffer them in some way and register a `flush` callback to write.
-    fn write_values<'a>(&mut self, _list: ValueList<'a>) -> Result<(), Error> {
+    fn write_values<'a>(&self, _list: ValueList<'a>) -> Result<(), Error> {
+        Err(Error::from(NotImplemented))
+    }
+
+    /// Collectd has noticed that a series stopped reporting values (eg: a disk was unplugged), or
+    /// a plugin explicitly declared it missing. The list contains the last values seen for the
+    /// series. Implementations that expect to be told of missing values need to have at least a
+    /// capability of `MISSING`.
+    fn missing_values<'a>(&self, _list: ValueList<'a>) -> Result<(), Error> {
         Err(Error::from(NotImplemented))
     }
 
     /// Flush values to be written that are older than given duration. If an identifier is given,
-    /// then only those buffered values should be flushed.
+    /// then only those buffered values should be flushed (see `Identifier::matches`).
     fn flush(
-        &mut self,
+        &self,
         _timeout: Option<Duration>,
-        _identifier: Option<&str>,
+        _identifier: Option<Identifier>,
     ) -> Result<(), Error> {
         Err(Error::from(NotImplemented))
     }
+
+    /// Collectd is giving you a notification (eg: from the threshold plugin), do with it as you
+    /// please. Implementations that expect to receive notifications need to have at least a
+    /// capability of `NOTIFICATION`.
+    fn notification<'a>(&self, _notif: Notification<'a>) -> Result<(), Error> {
+        Err(Error::from(NotImplemented))
+    }
+
+    /// Called when collectd is shutting down, after reads and writes have stopped, so this is the
+    /// place to drain buffers, close connections, and persist state. Log and notification
+    /// callbacks may still be invoked concurrently or afterwards, until collectd destroys all
+    /// callbacks. Plugins are shutdown in the order that they were registered. Requires a
+    /// capability of `SHUTDOWN`.
+    fn shutdown(&self) -> Result<(), Error> {
+        Err(Error::from(NotImplemented))
+    }
 }
 
-/// Sets up all the ffi entry points that collectd expects when given a `PluginManager`.
-#[macro_export]
-macro_rules! collectd_plugin {
-    ($type:ty) => {
-        // Let's us know if we've seen our config section before
-        static CONFIG_SEEN: ::std::sync::atomic::AtomicBool =
-            ::std::sync::atomic::AtomicBool::new(false);
-
-        // This is the main entry point that collectd looks for. Our plugin manager will register
-        // callbacks for configuration related to our name. It also registers a callback for
-        // initialization for when configuratio is absent or a single plugin wants to hold global
-        // data
-        #[no_mangle]
-        pub extern "C" fn module_register() {
-            use std::ffi::CString;
-            use $crate::bindings::{plugin_register_complex_config, plugin_register_init};
+/// A plugin that reports values to collectd. See `PluginBuilder`.
+pub trait ReadPlugin {
+    /// Same as `Plugin::read_values`
+    fn read_values(&self) -> Result<(), Error>;
+}
 
-            let s = CString::new(<$type as PluginManager>::name())
-                .expect("Plugin name to not contain nulls");
+/// A plugin that receives values that other plugins reported. See `PluginBuilder`.
+pub trait WritePlugin {
+    /// Same as `Plugin::write_values`
+    fn write_values<'a>(&self, list: ValueList<'a>) -> Result<(), Error>;
+}
 
-            unsafe {
-                plugin_register_complex_config(s.as_ptr(), Some(collectd_plugin_complex_config));
+/// A plugin that receives collectd's log messages. See `PluginBuilder`.
+pub trait LogPlugin {
+    /// Same as `Plugin::log`
+    fn log(&self, lvl: LogLevel, msg: &str) -> Result<(), Error>;
+}
 
-                plugin_register_init(s.as_ptr(), Some(collectd_plugin_init));
+/// A plugin that flushes buffered values. See `PluginBuilder`.
+pub trait FlushPlugin {
+    /// Same as `Plugin::flush`
+    fn flush(&self, timeout: Option<Duration>, identifier: Option<Identifier>)
+        -> Result<(), Error>;
+}
+
+/// A plugin that is told when a series stops reporting values. See `PluginBuilder`.
+pub trait MissingPlugin {
+    /// Same as `Plugin::missing_values`
+    fn missing_values<'a>(&self, list: ValueList<'a>) -> Result<(), Error>;
+}
+
+/// A plugin that receives notifications. See `PluginBuilder`.
+pub trait NotificationPlugin {
+    /// Same as `Plugin::notification`
+    fn notification<'a>(&self, notif: Notification<'a>) -> Result<(), Error>;
+}
+
+/// A plugin that initializes its own resources. See `PluginBuilder`.
+pub trait InitPlugin {
+    /// Same as `Plugin::initialize`
+    fn initialize(&self) -> Result<(), Error>;
+}
+
+/// A plugin that cleans up after itself when collectd shuts down. See `PluginBuilder`.
+pub trait ShutdownPlugin {
+    /// Same as `Plugin::shutdown`
+    fn shutdown(&self) -> Result<(), Error>;
+}
+
+/// Builds a `Plugin` out of a type that implements any of the `ReadPlugin`, `WritePlugin`,
+/// `LogPlugin`, `FlushPlugin`, `MissingPlugin`, `NotificationPlugin`, `InitPlugin`, and
+/// `ShutdownPlugin` traits. Each capability can only be added when the type
+/// implements the corresponding trait, and the plugin's capabilities are derived from the ones
+/// added, so the compiler guarantees that a plugin implements everything it advertises.
+///
+/// ```rust,no_run
+/// #[macro_use]
+/// extern crate collectd_plugin;
+/// extern crate failure;
+///
+/// use collectd_plugin::{ConfigItem, PluginBuilder, PluginManager, PluginRegistration, ReadPlugin,
+///                       RegistrationHandle, Value, ValueListBuilder};
+/// use failure::Error;
+///
+/// struct MyPlugin {
+///     handle: Option<RegistrationHandle>,
+/// }
+///
+/// impl ReadPlugin for MyPlugin {
+///     fn read_values(&self) -> Result<(), Error> {
+///         let values = vec![Value::Gauge(15.0), Value::Gauge(10.0), Value::Gauge(12.0)];
+///         ValueListBuilder::new("myplugin", "load")
+///             .values(&values)
+///             .submit()
+///     }
+/// }
+///
+/// struct MyManager;
+///
+/// impl PluginManager for MyManager {
+///     fn name() -> &'static str {
+///         "myplugin"
+///     }
+///
+///     fn plugins(_config: Option<&[ConfigItem]>) -> Result<PluginRegistration, Error> {
+///         let plugin = PluginBuilder::new(MyPlugin { handle: None })
+///             .read()
+///             .read_group("myplugin")
+///             .panic_limit(3)
+///             .registered(|plugin, handle| plugin.handle = Some(handle))
+///             .build();
+///         Ok(PluginRegistration::Single(plugin))
+///     }
+/// }
+///
+/// collectd_plugin!(MyManager);
+///
+/// # fn main() {
+/// # }
+/// ```
+pub struct PluginBuilder<T> {
+    plugin: BuiltPlugin<T>,
+}
+
+impl<T: Send + Sync + 'static> PluginBuilder<T> {
+    /// Starts building a plugin without any capabilities
+    pub fn new(plugin: T) -> PluginBuilder<T> {
+        PluginBuilder {
+            plugin: BuiltPlugin {
+                inner: plugin,
+                read: None,
+                write: None,
+                log: None,
+                flush: None,
+                missing: None,
+                notification: None,
+                init: None,
+                shutdown: None,
+                registered: None,
+                read_interval: None,
+                read_group: None,
+                panic_limit: None,
+            },
+        }
+    }
+
+    /// Registers the plugin to report values
+    pub fn read(mut self) -> PluginBuilder<T>
+    where
+        T: ReadPlugin,
+    {
+        self.plugin.read = Some(<T as ReadPlugin>::read_values);
+        self
+    }
+
+    /// Registers the plugin to receive values
+    pub fn write(mut self) -> PluginBuilder<T>
+    where
+        T: WritePlugin,
+    {
+        self.plugin.write = Some(<T as WritePlugin>::write_values);
+        self
+    }
+
+    /// Registers the plugin to receive log messages
+    pub fn log(mut self) -> PluginBuilder<T>
+    where
+        T: LogPlugin,
+    {
+        self.plugin.log = Some(<T as LogPlugin>::log);
+        self
+    }
+
+    /// Registers the plugin to flush values
+    pub fn flush(mut self) -> PluginBuilder<T>
+    where
+        T: FlushPlugin,
+    {
+        self.plugin.flush = Some(<T as FlushPlugin>::flush);
+        self
+    }
+
+    /// Registers the plugin to be told of missing values
+    pub fn missing(mut self) -> PluginBuilder<T>
+    where
+        T: MissingPlugin,
+    {
+        self.plugin.missing = Some(<T as MissingPlugin>::missing_values);
+        self
+    }
+
+    /// Registers the plugin to receive notifications
+    pub fn notification(mut self) -> PluginBuilder<T>
+    where
+        T: NotificationPlugin,
+    {
+        self.plugin.notification = Some(<T as NotificationPlugin>::notification);
+        self
+    }
+
+    /// Initializes the plugin before its callbacks are registered
+    pub fn init(mut self) -> PluginBuilder<T>
+    where
+        T: InitPlugin,
+    {
+        self.plugin.init = Some(<T as InitPlugin>::initialize);
+        self
+    }
+
+    /// Shuts the plugin down when collectd shuts down
+    pub fn shutdown(mut self) -> PluginBuilder<T>
+    where
+        T: ShutdownPlugin,
+    {
+        self.plugin.shutdown = Some(<T as ShutdownPlugin>::shutdown);
+        self
+    }
+
+    /// Hands the plugin its registration before any of its callbacks are registered. Same as
+    /// `Plugin::registered`
+    pub fn registered(mut self, f: fn(&mut T, RegistrationHandle)) -> PluginBuilder<T> {
+        self.plugin.registered = Some(f);
+        self
+    }
+
+    /// The interval at which the plugin is read. Same as `Plugin::read_interval`
+    pub fn read_interval(mut self, interval: Duration) -> PluginBuilder<T> {
+        self.plugin.read_interval = Some(interval);
+        self
+    }
+
+    /// The read group that the plugin is read under. Same as `Plugin::read_group`
+    pub fn read_group<S: Into<String>>(mut self, group: S) -> PluginBuilder<T> {
+        self.plugin.read_group = Some(group.into());
+        self
+    }
+
+    /// The number of panics after which the plugin is disabled. Same as `Plugin::panic_limit`
+    pub fn panic_limit(mut self, limit: usize) -> PluginBuilder<T> {
+        self.plugin.panic_limit = Some(limit);
+        self
+    }
+
+    /// Finishes the plugin, which is ready to be returned in a `PluginRegistration`
+    pub fn build(self) -> Box<Plugin> {
+        Box::new(self.plugin)
+    }
+}
+
+// A plugin composed out of the capability traits. Function pointers are captured when a
+// capability is added, as that is the only point where it is known which traits are implemented.
+type ReadFn<T> = fn(&T) -> Result<(), Error>;
+type WriteFn<T> = for<'a> fn(&T, ValueList<'a>) -> Result<(), Error>;
+type LogFn<T> = fn(&T, LogLevel, &str) -> Result<(), Error>;
+type FlushFn<T> = for<'a> fn(&T, Option<Duration>, Option<Identifier<'a>>) -> Result<(), Error>;
+type MissingFn<T> = for<'a> fn(&T, ValueList<'a>) -> Result<(), Error>;
+type NotificationFn<T> = for<'a> fn(&T, Notification<'a>) -> Result<(), Error>;
+type InitFn<T> = fn(&T) -> Result<(), Error>;
+type ShutdownFn<T> = fn(&T) -> Result<(), Error>;
+type RegisteredFn<T> = fn(&mut T, RegistrationHandle);
+
+struct BuiltPlugin<T> {
+    inner: T,
+    read: Option<ReadFn<T>>,
+    write: Option<WriteFn<T>>,
+    log: Option<LogFn<T>>,
+    flush: Option<FlushFn<T>>,
+    missing: Option<MissingFn<T>>,
+    notification: Option<NotificationFn<T>>,
+    init: Option<InitFn<T>>,
+    shutdown: Option<ShutdownFn<T>>,
+    registered: Option<RegisteredFn<T>>,
+    read_interval: Option<Duration>,
+    read_group: Option<String>,
+    panic_limit: Option<usize>,
+}
+
+impl<T: Send + Sync> Plugin for BuiltPlugin<T> {
+    fn capabilities(&self) -> PluginCapabilities {
+        let mut capabilities = PluginCapabilities::default();
+        capabilities.set(PluginCapabilities::READ, self.read.is_some());
+        capabilities.set(PluginCapabilities::WRITE, self.write.is_some());
+        capabilities.set(PluginCapabilities::LOG, self.log.is_some());
+        capabilities.set(PluginCapabilities::FLUSH, self.flush.is_some());
+        capabilities.set(PluginCapabilities::MISSING, self.missing.is_some());
+        capabilities.set(
+            PluginCapabilities::NOTIFICATION,
+            self.notification.is_some(),
+        );
+        capabilities.set(PluginCapabilities::INIT, self.init.is_some());
+        capabilities.set(PluginCapabilities::SHUTDOWN, self.shutdown.is_some());
+        capabilities
+    }
+
+    fn registered(&mut self, handle: RegistrationHandle) {
+        if let Some(f) = self.registered {
+            f(&mut self.inner, handle);
+        }
+    }
+
+    fn read_interval(&self) -> Option<Duration> {
+        self.read_interval
+    }
+
+    fn read_group(&self) -> Option<&str> {
+        self.read_group.as_ref().map(AsRef::as_ref)
+    }
+
+    fn panic_limit(&self) -> Option<usize> {
+        self.panic_limit
+    }
+
+    fn read_values(&self) -> Result<(), Error> {
+        match self.read {
+            Some(f) => f(&self.inner),
+            None => Err(Error::from(NotImplemented)),
+        }
+    }
+
+    fn write_values<'a>(&self, list: ValueList<'a>) -> Result<(), Error> {
+        match self.write {
+            Some(f) => f(&self.inner, list),
+            None => Err(Error::from(NotImplemented)),
+        }
+    }
+
+    fn log(&self, lvl: LogLevel, msg: &str) -> Result<(), Error> {
+        match self.log {
+            Some(f) => f(&self.inner, lvl, msg),
+            None => Err(Error::from(NotImplemented)),
+        }
+    }
+
+    fn flush(
+        &self,
+        timeout: Option<Duration>,
+        identifier: Option<Identifier>,
+    ) -> Result<(), Error> {
+        match self.flush {
+            Some(f) => f(&self.inner, timeout, identifier),
+            None => Err(Error::from(NotImplemented)),
+        }
+    }
+
+    fn missing_values<'a>(&self, list: ValueList<'a>) -> Result<(), Error> {
+        match self.missing {
+            Some(f) => f(&self.inner, list),
+            None => Err(Error::from(NotImplemented)),
+        }
+    }
+
+    fn notification<'a>(&self, notif: Notification<'a>) -> Result<(), Error> {
+        match self.notification {
+            Some(f) => f(&self.inner, notif),
+            None => Err(Error::from(NotImplemented)),
+        }
+    }
+
+    fn initialize(&self) -> Result<(), Error> {
+        match self.init {
+            Some(f) => f(&self.inner),
+            None => Err(Error::from(NotImplemented)),
+        }
+    }
+
+    fn shutdown(&self) -> Result<(), Error> {
+        match self.shutdown {
+            Some(f) => f(&self.inner),
+            None => Err(Error::from(NotImplemented)),
+        }
+    }
+}
+
+/// Looks for collectd's standard `Interval` key (in seconds) in a plugin's configuration block,
+/// which serves as the default read interval for all plugins that the block registers.
+#[doc(hidden)]
+pub fn config_read_interval(config: &[ConfigItem]) -> Result<Option<Duration>, Error> {
+    let item = match config
+        .iter()
+        .find(|x| x.key.eq_ignore_ascii_case("Interval"))
+    {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+
+    match item.values.first() {
+        Some(&ConfigValue::Number(x)) if item.values.len() == 1 && x > 0.0 => {
+            Ok(Some(Duration::nanoseconds((x * 1e9) as i64)))
+        }
+        _ => Err(format_err!(
+            "Interval must be a single positive number of seconds"
+        )),
+    }
+}
+
+lazy_static! {
+    static ref CONFIG_BLOCKS: Mutex<Vec<(&'static str, OwnedConfigItem)>> = Mutex::new(Vec::new());
+}
+
+/// Holds onto a plugin's config block until collectd initializes the plugin, as collectd frees
+/// the config once the config callback returns and a plugin may be configured in several blocks
+#[doc(hidden)]
+pub fn stash_config(manager: &'static str, block: &ConfigItem) {
+    let mut blocks = CONFIG_BLOCKS.lock().unwrap();
+    blocks.push((manager, OwnedConfigItem::from(block)));
+}
+
+/// Removes and returns all config blocks seen for the given manager (in the order they were seen)
+#[doc(hidden)]
+pub fn take_config(manager: &str) -> Option<Vec<OwnedConfigItem>> {
+    let mut blocks = CONFIG_BLOCKS.lock().unwrap();
+    let (ours, theirs): (Vec<_>, Vec<_>) = blocks.drain(..).partition(|x| x.0 == manager);
+    *blocks = theirs;
+
+    if ours.is_empty() {
+        None
+    } else {
+        Some(ours.into_iter().map(|x| x.1).collect())
+    }
+}
+
+lazy_static! {
+    static ref CONFIG_PAIRS: Mutex<Vec<(&'static str, String, String)>> = Mutex::new(Vec::new());
+}
+
+/// Holds onto a key and value from a plugin's simple config until collectd initializes the plugin
+#[doc(hidden)]
+pub fn stash_config_pair(manager: &'static str, key: &str, value: &str) {
+    let mut pairs = CONFIG_PAIRS.lock().unwrap();
+    pairs.push((manager, key.to_string(), value.to_string()));
+}
+
+/// Removes all simple config pairs seen for the given manager and returns them as a single config
+/// block, with an item (containing a single string value) for each pair
+#[doc(hidden)]
+pub fn take_config_pairs(manager: &str) -> Option<OwnedConfigItem> {
+    let mut pairs = CONFIG_PAIRS.lock().unwrap();
+    let (ours, theirs): (Vec<_>, Vec<_>) = pairs.drain(..).partition(|x| x.0 == manager);
+    *pairs = theirs;
+
+    if ours.is_empty() {
+        return None;
+    }
+
+    let children = ours
+        .into_iter()
+        .map(|(_, key, value)| OwnedConfigItem {
+            key,
+            values: vec![OwnedConfigValue::String(value)],
+            children: vec![],
+        })
+        .collect();
+
+    Some(OwnedConfigItem {
+        key: String::from("Plugin"),
+        values: vec![OwnedConfigValue::String(manager.to_string())],
+        children,
+    })
+}
+
+/// Merges the contents of a plugin's config blocks into one view, as if they had all been written
+/// in a single block (in the order they were seen), so a key repeated across blocks is repeated in
+/// the merged view. The exception is collectd's standard `Interval` key, which can only hold one
+/// value: it may be repeated (eg: in every file of a one file per instance setup) as long as each
+/// block sets it to the same value, and is kept once.
+#[doc(hidden)]
+pub fn merge_config<'a>(blocks: &'a [OwnedConfigItem]) -> Result<Vec<ConfigItem<'a>>, Error> {
+    let mut interval: Option<(&OwnedConfigItem, usize)> = None;
+    let mut merged = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        for item in &block.children {
+            if item.key.eq_ignore_ascii_case("Interval") {
+                if let Some((first, j)) = interval {
+                    if first.values != item.values {
+                        return Err(format_err!(
+                            "conflicting config: `{}` is set to different values in block {} and \
+                             block {}",
+                            item.key,
+                            j + 1,
+                            i + 1
+                        ));
+                    }
+
+                    continue;
+                }
+
+                interval = Some((item, i));
+            }
+
+            merged.push(item.as_config_item());
+        }
+    }
+
+    Ok(merged)
+}
+
+/// A manager's plugins, along with the name that each is registered under
+#[doc(hidden)]
+pub type NamedPlugins = Vec<(String, Box<Plugin>)>;
+
+/// Names each of a manager's plugins after the manager (and the plugin's id, if there are many).
+/// Collectd replaces a callback registered under a name that is taken and frees the old one, which
+/// another thread may be running by the time plugins are registered, so names must be unique.
+#[doc(hidden)]
+pub fn name_plugins(
+    manager: &str,
+    registration: PluginRegistration,
+) -> Result<NamedPlugins, Error> {
+    let plugins: NamedPlugins = match registration {
+        PluginRegistration::Single(pl) => vec![(manager.to_string(), pl)],
+        PluginRegistration::Multiple(v) => v
+            .into_iter()
+            .map(|(id, pl)| (format!("{}/{}", manager, id), pl))
+            .collect(),
+    };
+
+    for (i, plugin) in plugins.iter().enumerate() {
+        if plugins[..i].iter().any(|x| x.0 == plugin.0) {
+            return Err(format_err!(
+                "more than one plugin is registered as {}",
+                plugin.0
+            ));
+        }
+    }
+
+    Ok(plugins)
+}
+
+/// A plugin instance that has been registered with collectd. Collectd only hands back a plugin in
+/// callbacks that accept user data, so callbacks without it (like shutdown) find plugins here.
+/// The registry holds its own reference to the plugin, so a tracked plugin is never freed before
+/// it is untracked.
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct RegisteredPlugin {
+    /// Name of the `PluginManager` that registered the plugin
+    pub manager: &'static str,
+
+    /// Name that the plugin was registered under
+    pub name: String,
+
+    /// Capabilities that the plugin advertised when it was registered
+    pub capabilities: PluginCapabilities,
+
+    /// The plugin, shared with the user data of each of its callbacks
+    pub plugin: Arc<PluginEntry>,
+}
+
+lazy_static! {
+    static ref REGISTERED_PLUGINS: Mutex<Vec<RegisteredPlugin>> = Mutex::new(Vec::new());
+}
+
+/// Records a plugin that has been registered with collectd
+#[doc(hidden)]
+pub fn track_plugin(
+    manager: &'static str,
+    name: &str,
+    capabilities: PluginCapabilities,
+    plugin: Arc<PluginEntry>,
+) {
+    let mut plugins = REGISTERED_PLUGINS.lock().unwrap();
+    plugins.push(RegisteredPlugin {
+        manager,
+        name: name.to_string(),
+        capabilities,
+        plugin,
+    });
+}
+
+/// Returns all plugins registered by the given manager (in registration order)
+#[doc(hidden)]
+pub fn tracked_plugins(manager: &str) -> Vec<RegisteredPlugin> {
+    let plugins = REGISTERED_PLUGINS.lock().unwrap();
+    plugins
+        .iter()
+        .filter(|x| x.manager == manager)
+        .cloned()
+        .collect()
+}
+
+/// Removes and returns the plugin registered by the given manager under the given name
+#[doc(hidden)]
+pub fn untrack_plugin(manager: &str, name: &str) -> Option<RegisteredPlugin> {
+    let mut plugins = REGISTERED_PLUGINS.lock().unwrap();
+    plugins
+        .iter()
+        .position(|x| x.manager == manager && x.name == name)
+        .map(|i| plugins.remove(i))
+}
+
+/// Removes and returns all plugins registered by the given manager (in registration order)
+#[doc(hidden)]
+pub fn untrack_plugins(manager: &str) -> Vec<RegisteredPlugin> {
+    let mut plugins = REGISTERED_PLUGINS.lock().unwrap();
+    let (ours, theirs) = plugins.drain(..).partition(|x| x.manager == manager);
+    *plugins = theirs;
+    ours
+}
+
+/// A handle to a plugin's registration with collectd, which allows the plugin to be unregistered
+/// at runtime. Handles are cheap to clone, and all clones refer to the same registration.
+#[derive(Clone, Debug)]
+pub struct RegistrationHandle {
+    manager: &'static str,
+    name: String,
+    unregistered: Arc<AtomicBool>,
+    read_registered: Arc<AtomicBool>,
+}
+
+impl RegistrationHandle {
+    #[doc(hidden)]
+    pub fn new(manager: &'static str, name: &str) -> Self {
+        RegistrationHandle {
+            manager,
+            name: name.to_string(),
+            unregistered: Arc::new(AtomicBool::new(false)),
+            read_registered: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// The name that the plugin's callbacks are registered under
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns true if the plugin has been unregistered
+    pub fn is_unregistered(&self) -> bool {
+        self.unregistered.load(Ordering::SeqCst)
+    }
+
+    /// Unregisters the plugin, so none of its callbacks are passed through to it anymore and it
+    /// won't be shutdown. Calling this more than once has no effect, and it is safe to call from
+    /// any thread, including from within the plugin's own callbacks.
+    ///
+    /// Only the read callback is removed from collectd, which happens right away. Collectd walks
+    /// its lists of write, log, flush, missing, and notification callbacks without a lock, so
+    /// removing one of those while collectd is running could free it from under another thread.
+    /// They stay registered (and do nothing) until collectd shuts down, which is when the plugin
+    /// is dropped.
+    pub fn unregister(&self) {
+        if self.unregistered.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        untrack_plugin(self.manager, &self.name);
+        self.unregister_read();
+    }
+
+    /// Records that the plugin's read callback has been registered with collectd
+    #[doc(hidden)]
+    pub fn read_registered(&self) {
+        self.read_registered.store(true, Ordering::SeqCst);
+    }
+
+    /// Removes the plugin's read callback from collectd, if it has been registered. Calling this
+    /// more than once has no effect. Collectd guards its read callbacks with a lock and defers
+    /// freeing one that is running, so this is safe to call from any thread.
+    #[doc(hidden)]
+    pub fn unregister_read(&self) {
+        if !self.read_registered.swap(false, Ordering::SeqCst) {
+            return;
+        }
+
+        let s = CString::new(self.name.as_str()).expect("Plugin name to not contain nulls");
+        unsafe {
+            plugin_unregister_read(s.as_ptr());
+        }
+    }
+}
+
+/// A registered plugin. Collectd frees user data once per registered callback, so each callback
+/// is handed its own reference counted pointer to the entry, and the plugin is dropped exactly
+/// once, when the last callback is unregistered.
+#[doc(hidden)]
+pub struct PluginEntry {
+    pub plugin: Box<Plugin>,
+    pub handle: RegistrationHandle,
+    panic_limit: Option<usize>,
+    panics: AtomicUsize,
+    disabled: AtomicBool,
+}
+
+impl PluginEntry {
+    pub fn new(plugin: Box<Plugin>, handle: RegistrationHandle) -> Self {
+        let panic_limit = plugin.panic_limit();
+        PluginEntry {
+            plugin,
+            handle,
+            panic_limit,
+            panics: AtomicUsize::new(0),
+            disabled: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns true if the plugin panicked too many times or was unregistered, so callbacks
+    /// should no longer be passed through
+    pub fn is_disabled(&self) -> bool {
+        self.disabled.load(Ordering::SeqCst) || self.handle.is_unregistered()
+    }
+
+    /// Stops passing callbacks through to the plugin
+    pub fn disable(&self) {
+        self.disabled.store(true, Ordering::SeqCst);
+    }
+
+    /// Records a panic and returns the total number of panics if the plugin should now be disabled
+    pub fn record_panic(&self) -> Option<usize> {
+        let panics = self.panics.fetch_add(1, Ordering::SeqCst) + 1;
+        match self.panic_limit {
+            Some(limit) if panics >= limit && !self.disabled.swap(true, Ordering::SeqCst) => {
+                Some(panics)
             }
+            _ => None,
         }
+    }
+}
+
+/// Extracts the message that a panic was raised with
+#[doc(hidden)]
+pub fn panic_message(payload: &(Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "unknown panic payload"
+    }
+}
+
+/// Sets up all the ffi entry points that collectd expects when given one or more
+/// `PluginManager`s. A single library can host several managers (eg:
+/// `collectd_plugin!(CpuManager, DiskManager)`), each registering its own config, init, and
+/// callbacks under its own name. For collectd to know about every name, symlink the library as
+/// each of the names and add a `LoadPlugin` line for each.
+///
+/// Collectd loads the library only once, so every manager is registered on the first
+/// `LoadPlugin` line and runs in that line's context: options of later `<LoadPlugin>` blocks
+/// (eg: `Interval`) are ignored. Set a manager's read interval with an `Interval` key in its
+/// `<Plugin>` block instead.
+///
+/// A manager's plugins are created and registered when collectd initializes the manager, once all
+/// of its config blocks have been seen. By then collectd has started its write threads, which
+/// (like any thread that logs) walk the lists of write, log, flush, missing, and notification
+/// callbacks without a lock while the plugins' callbacks are being added. Collectd only appends to
+/// those lists, the same as when a C plugin registers callbacks from its init callback, and each
+/// plugin is fully set up (and initialized) before its first callback is registered. Registering
+/// under a name that is already taken would free a callback that may be running, so a manager
+/// whose plugins don't have unique names registers none of them.
+#[macro_export]
+macro_rules! collectd_plugin {
+    (@manager $type:ty) => {{
+        use std::ffi::CString;
+        use $crate::bindings::{
+            plugin_register_complex_config, plugin_register_init, plugin_register_shutdown,
+        };
 
         // Logs an error with a description and all the causes
         fn collectd_log_err(desc: &str, err: &Error) {
@@ -167,30 +1264,129 @@ macro_rules! collectd_plugin {
             );
         }
 
+        // Unwinding a panic into collectd's C code is undefined behavior (and usually aborts the
+        // daemon), so every entry point catches panics, logs them, and reports an error instead
+        fn collectd_catch<F>(desc: &str, f: F) -> std::os::raw::c_int
+        where
+            F: FnOnce() -> std::os::raw::c_int,
+        {
+            match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+                Ok(result) => result,
+                Err(ref e) => {
+                    $crate::collectd_log(
+                        $crate::LogLevel::Error,
+                        &format!(
+                            "{} {} panicked: {}",
+                            <$type as $crate::PluginManager>::name(),
+                            desc,
+                            $crate::panic_message(&**e)
+                        ),
+                    );
+                    -1
+                }
+            }
+        }
+
+        // Passes a callback through to a plugin (unless the plugin has been disabled), while
+        // counting the plugin's panics so that it can be disabled once it reaches its limit
+        fn collectd_plugin_catch<F>(
+            entry: &$crate::PluginEntry,
+            desc: &str,
+            f: F,
+        ) -> std::os::raw::c_int
+        where
+            F: FnOnce(&$crate::Plugin) -> std::os::raw::c_int,
+        {
+            // Collectd can't safely remove most callbacks at runtime, so a disabled plugin keeps
+            // them (all but the read callback) and they quietly do nothing
+            if entry.is_disabled() {
+                return 0;
+            }
+
+            let plugin = &*entry.plugin;
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(plugin)));
+
+            match result {
+                Ok(result) => result,
+                Err(ref e) => {
+                    $crate::collectd_log(
+                        $crate::LogLevel::Error,
+                        &format!(
+                            "{} {} panicked: {}",
+                            entry.handle.name(),
+                            desc,
+                            $crate::panic_message(&**e)
+                        ),
+                    );
+
+                    if let Some(panics) = entry.record_panic() {
+                        $crate::collectd_log(
+                            $crate::LogLevel::Error,
+                            &format!(
+                                "{} disabled after panicking {} times",
+                                entry.handle.name(),
+                                panics
+                            ),
+                        );
+
+                        // The read callback is the only one that collectd can safely remove while
+                        // running; the others stay registered and pass nothing through
+                        entry.handle.unregister_read();
+                    }
+                    -1
+                }
+            }
+        }
+
+        // Each callback's user data holds a reference to the plugin. We take out another one so
+        // that the plugin outlives the callback, even if the callback unregisters the plugin
         unsafe fn collectd_user_data(
             dt: *mut $crate::bindings::user_data_t,
-        ) -> Box<Box<$crate::Plugin>> {
-            Box::from_raw((*dt).data as *mut Box<$crate::Plugin>)
+        ) -> std::sync::Arc<$crate::PluginEntry> {
+            let entry = &*((*dt).data as *const std::sync::Arc<$crate::PluginEntry>);
+            std::sync::Arc::clone(entry)
         }
 
         extern "C" fn collectd_plugin_read(
             dt: *mut $crate::bindings::user_data_t,
         ) -> std::os::raw::c_int {
-            let mut plugin = unsafe { collectd_user_data(dt) };
-            let result = if let Err(ref e) = plugin.read_values() {
-                collectd_log_err("read", e);
-                -1
-            } else {
-                0
-            };
-
-            std::mem::forget(plugin);
-            result
+            let entry = unsafe { collectd_user_data(dt) };
+            collectd_plugin_catch(&entry, "read", |plugin| match plugin.read_values() {
+                Ok(()) => 0,
+                Err(ref e) => match $crate::ReadError::of(e) {
+                    Some($crate::ReadError::Skip) => 0,
+                    Some($crate::ReadError::Transient) => {
+                        collectd_log_err("read", e);
+                        0
+                    }
+                    Some($crate::ReadError::Permanent) => {
+                        collectd_log_err("read", e);
+                        entry.disable();
+                        entry.handle.unregister_read();
+                        -1
+                    }
+                    None => {
+                        collectd_log_err("read", e);
+                        -1
+                    }
+                },
+            })
         }
 
         unsafe extern "C" fn collectd_plugin_free_user_data(raw: *mut ::std::os::raw::c_void) {
-            let ptr = raw as *mut Box<$crate::Plugin>;
-            Box::from_raw(ptr);
+            let ptr = raw as *mut std::sync::Arc<$crate::PluginEntry>;
+            collectd_catch("free", || {
+                Box::from_raw(ptr);
+                0
+            });
+        }
+
+        thread_local! {
+            // The plugins that are handling a log message on this thread, so that messages logged
+            // by a plugin itself (or a panic in the plugin) don't recursively feed back into that
+            // plugin. Other log plugins still receive them
+            static COLLECTD_LOGGING: ::std::cell::RefCell<Vec<usize>> =
+                ::std::cell::RefCell::default();
         }
 
         extern "C" fn collectd_plugin_log(
@@ -199,170 +1395,378 @@ macro_rules! collectd_plugin {
             dt: *mut $crate::bindings::user_data_t,
         ) {
             use std::ffi::CStr;
-            let mut plugin = unsafe { collectd_user_data(dt) };
-            let msg = unsafe { CStr::from_ptr(message).to_string_lossy() };
-            let log_level = $crate::LogLevel::try_from(severity as u32);
-            if let Some(lvl) = log_level {
-                if let Err(ref e) = plugin.log(lvl, std::ops::Deref::deref(&msg)) {
-                    collectd_log_err("logging", e);
-                }
-            } else {
-                $crate::collectd_log(
-                    $crate::LogLevel::Error,
-                    &format!(
-                        "Unrecognized severity log level: {} for {}",
-                        severity,
-                        <$type as PluginManager>::name()
-                    ),
-                );
+            let entry = unsafe { collectd_user_data(dt) };
+            let key = &*entry as *const $crate::PluginEntry as usize;
+            if COLLECTD_LOGGING.with(|x| x.borrow().contains(&key)) {
+                return;
             }
 
-            std::mem::forget(plugin);
+            COLLECTD_LOGGING.with(|x| x.borrow_mut().push(key));
+            collectd_plugin_catch(&entry, "log", |plugin| {
+                let msg = unsafe { CStr::from_ptr(message).to_string_lossy() };
+                let log_level = $crate::LogLevel::try_from(severity as u32);
+                if let Some(lvl) = log_level {
+                    if let Err(ref e) = plugin.log(lvl, std::ops::Deref::deref(&msg)) {
+                        collectd_log_err("logging", e);
+                    }
+                } else {
+                    $crate::collectd_log(
+                        $crate::LogLevel::Error,
+                        &format!(
+                            "Unrecognized severity log level: {} for {}",
+                            severity,
+                            <$type as $crate::PluginManager>::name()
+                        ),
+                    );
+                }
+                0
+            });
+
+            COLLECTD_LOGGING.with(|x| x.borrow_mut().retain(|&k| k != key));
+        }
+
+        extern "C" fn collectd_plugin_write(
+            ds: *const $crate::bindings::data_set_t,
+            vl: *const $crate::bindings::value_list_t,
+            dt: *mut $crate::bindings::user_data_t,
+        ) -> std::os::raw::c_int {
+            let entry = unsafe { collectd_user_data(dt) };
+            collectd_plugin_catch(&entry, "write", |plugin| {
+                let list = unsafe { $crate::ValueList::from(&*ds, &*vl) };
+                if let Err(ref e) = list {
+                    collectd_log_err("unable to decode collectd data", e);
+                    return -1;
+                }
+
+                if let Err(ref e) = plugin.write_values(list.unwrap()) {
+                    collectd_log_err("writing", e);
+                    -1
+                } else {
+                    0
+                }
+            })
+        }
+
+        extern "C" fn collectd_plugin_missing(
+            vl: *const $crate::bindings::value_list_t,
+            dt: *mut $crate::bindings::user_data_t,
+        ) -> std::os::raw::c_int {
+            let entry = unsafe { collectd_user_data(dt) };
+            collectd_plugin_catch(&entry, "missing", |plugin| {
+                // Unlike writes, collectd doesn't pass the data set along with missing values, so
+                // we look it up ourselves
+                let ds = unsafe { $crate::bindings::plugin_get_ds((*vl).type_.as_ptr()) };
+                if ds.is_null() {
+                    $crate::collectd_log(
+                        $crate::LogLevel::Error,
+                        &format!(
+                            "unable to find data set of missing values for {}",
+                            <$type as $crate::PluginManager>::name()
+                        ),
+                    );
+                    return -1;
+                }
+
+                let list = unsafe { $crate::ValueList::from(&*ds, &*vl) };
+                if let Err(ref e) = list {
+                    collectd_log_err("unable to decode collectd data", e);
+                    return -1;
+                }
+
+                if let Err(ref e) = plugin.missing_values(list.unwrap()) {
+                    collectd_log_err("missing", e);
+                    -1
+                } else {
+                    0
+                }
+            })
+        }
+
+        extern "C" fn collectd_plugin_notification(
+            notif: *const $crate::bindings::notification_t,
+            dt: *mut $crate::bindings::user_data_t,
+        ) -> std::os::raw::c_int {
+            let entry = unsafe { collectd_user_data(dt) };
+            collectd_plugin_catch(&entry, "notification", |plugin| {
+                let notification = unsafe { $crate::Notification::from(&*notif) };
+                if let Err(ref e) = notification {
+                    collectd_log_err("unable to decode collectd notification", e);
+                    return -1;
+                }
+
+                if let Err(ref e) = plugin.notification(notification.unwrap()) {
+                    collectd_log_err("notification", e);
+                    -1
+                } else {
+                    0
+                }
+            })
+        }
+
+        extern "C" fn collectd_plugin_init() -> std::os::raw::c_int {
+            collectd_catch("init", collectd_init_all_plugins)
         }
 
-        extern "C" fn collectd_plugin_write(
-            ds: *const $crate::bindings::data_set_t,
-            vl: *const $crate::bindings::value_list_t,
-            dt: *mut $crate::bindings::user_data_t,
-        ) -> std::os::raw::c_int {
-            let mut plugin = unsafe { collectd_user_data(dt) };
-            let list = unsafe { $crate::ValueList::from(&*ds, &*vl) };
-            if let Err(ref e) = list {
-                collectd_log_err("unable to decode collectd data", e);
-                std::mem::forget(plugin);
-                return -1;
-            }
+        fn collectd_init_all_plugins() -> std::os::raw::c_int {
+            $crate::start_executor();
 
-            let result = if let Err(ref e) = plugin.write_values(list.unwrap()) {
-                collectd_log_err("writing", e);
-                -1
+            // All of our config blocks have been seen by now, so the plugins can be created
+            let name = <$type as $crate::PluginManager>::name();
+            let blocks = if <$type as $crate::PluginManager>::config_keys().is_empty() {
+                $crate::take_config(name)
             } else {
-                0
+                $crate::take_config_pairs(name).map(|x| vec![x])
             };
-            std::mem::forget(plugin);
-            result
-        }
 
-        extern "C" fn collectd_plugin_init() -> std::os::raw::c_int {
-            let mut result = if !CONFIG_SEEN.swap(true, ::std::sync::atomic::Ordering::Relaxed) {
-                collectd_register_all_plugins(None)
-            } else {
-                0
+            let created = match blocks {
+                None => collectd_create_all_plugins(None),
+                Some(ref blocks) => $crate::merge_config(blocks)
+                    .and_then(|config| collectd_create_all_plugins(Some(&config))),
+            };
+
+            let mut result = 0;
+            let (interval, plugins) = match created {
+                Ok(created) => created,
+                Err(ref e) => {
+                    result = -1;
+                    collectd_log_err("collectd config", e);
+                    (None, Vec::new())
+                }
             };
 
-            let capabilities = <$type as PluginManager>::capabilities();
+            for set in <$type as $crate::PluginManager>::data_sets() {
+                if let Err(ref e) = set.register() {
+                    result = -1;
+                    collectd_log_err(&format!("{} data set", set.type_), e);
+                }
+            }
+
+            let capabilities = <$type as $crate::PluginManager>::capabilities();
             if capabilities.intersects($crate::PluginManagerCapabilities::INIT) {
-                if let Err(ref e) = <$type as PluginManager>::initialize() {
+                if let Err(ref e) = <$type as $crate::PluginManager>::initialize() {
                     result = -1;
                     collectd_log_err("init", e);
                 }
             }
 
+            // Collectd's write threads are running by now, see the docs of `collectd_plugin!` on
+            // why registering callbacks from here is sound
+            for (name, plugin) in plugins {
+                collectd_plugin_registration(&name, plugin, interval);
+            }
+
             result
         }
 
+        extern "C" fn collectd_plugin_shutdown() -> std::os::raw::c_int {
+            collectd_catch("shutdown", || {
+                let mut result = 0;
+
+                // Collectd has stopped all reads and writes before invoking shutdown, but log and
+                // notification callbacks may still fire until collectd destroys all callbacks.
+                // Untracking drops the registry's reference to the plugins, and the rest are
+                // dropped as collectd frees the user data of each callback
+                let plugins = $crate::untrack_plugins(<$type as $crate::PluginManager>::name());
+                for registered in plugins {
+                    if !registered.capabilities.has_shutdown() {
+                        continue;
+                    }
+
+                    let entry = &registered.plugin;
+                    result |= collectd_plugin_catch(&entry, "shutdown", |plugin| {
+                        if let Err(ref e) = plugin.shutdown() {
+                            collectd_log_err(&format!("{} shutdown", registered.name), e);
+                            -1
+                        } else {
+                            0
+                        }
+                    });
+                }
+
+                let capabilities = <$type as $crate::PluginManager>::capabilities();
+                if capabilities.intersects($crate::PluginManagerCapabilities::SHUTDOWN) {
+                    if let Err(ref e) = <$type as $crate::PluginManager>::shutdown() {
+                        result = -1;
+                        collectd_log_err("shutdown", e);
+                    }
+                }
+
+                $crate::stop_executor();
+                result
+            })
+        }
+
         extern "C" fn collectd_plugin_flush(
             timeout: $crate::bindings::cdtime_t,
             identifier: *const std::os::raw::c_char,
             dt: *mut $crate::bindings::user_data_t,
         ) -> std::os::raw::c_int {
-            use std::ffi::CStr;
-            let mut plugin = unsafe { collectd_user_data(dt) };
+            let entry = unsafe { collectd_user_data(dt) };
+            collectd_plugin_catch(&entry, "flush", |plugin| {
+                let dur = if timeout == 0 {
+                    None
+                } else {
+                    Some($crate::CdTime::from(timeout).into())
+                };
 
-            let dur = if timeout == 0 {
-                None
-            } else {
-                Some($crate::CdTime::from(timeout).into())
-            };
+                let ident = match unsafe { $crate::flush_identifier(identifier) } {
+                    Ok(ident) => ident,
+                    Err(ref e) => {
+                        collectd_log_err("flush identifier", e);
+                        return -1;
+                    }
+                };
 
-            let result = if let Ok(ident) = unsafe { CStr::from_ptr(identifier) }.to_str() {
-                if let Err(ref e) = plugin.flush(dur, $crate::empty_to_none(ident)) {
+                if let Err(ref e) = plugin.flush(dur, ident) {
                     collectd_log_err("flush", e);
                     -1
                 } else {
                     0
                 }
-            } else {
-                -1
-            };
-
-            std::mem::forget(plugin);
-            result
+            })
         }
 
         extern "C" fn collectd_plugin_complex_config(
             config: *mut $crate::bindings::oconfig_item_t,
         ) -> std::os::raw::c_int {
-            // If we've already seen the config, let's error out as one shouldn't use multiple
-            // sections of configuration (group them under nodes like write_graphite)
-            if CONFIG_SEEN.swap(true, ::std::sync::atomic::Ordering::Relaxed) {
-                $crate::collectd_log(
-                    $crate::LogLevel::Error,
-                    &format!(
-                        "already seen a config section for {}",
-                        <$type as PluginManager>::name()
-                    ),
-                );
-                return -1;
-            }
-
-            match unsafe { $crate::ConfigItem::from(&*config) } {
-                Ok(config) => collectd_register_all_plugins(Some(&config.children)),
-                Err(ref e) => {
-                    collectd_log_err("collectd config conversion", e);
-                    -1
+            // Collectd frees the config after this callback, and there may be more blocks to
+            // come, so the config is copied until the plugins are created on init
+            collectd_catch("config", || {
+                match unsafe { $crate::ConfigItem::from(&*config) } {
+                    Ok(config) => {
+                        $crate::stash_config(<$type as $crate::PluginManager>::name(), &config);
+                        0
+                    }
+                    Err(ref e) => {
+                        collectd_log_err("collectd config conversion", e);
+                        -1
+                    }
                 }
-            }
+            })
         }
 
-        fn collectd_register_all_plugins(
-            config: Option<&[$crate::ConfigItem]>,
+        unsafe extern "C" fn collectd_plugin_simple_config(
+            key: *const std::os::raw::c_char,
+            value: *const std::os::raw::c_char,
         ) -> std::os::raw::c_int {
-            match <$type as PluginManager>::plugins(config) {
-                Ok(registration) => {
-                    match registration {
-                        $crate::PluginRegistration::Single(pl) => {
-                            collectd_plugin_registration(
-                                <$type as $crate::PluginManager>::name(),
-                                pl,
-                            );
-                        }
-                        $crate::PluginRegistration::Multiple(v) => {
-                            for (id, pl) in v {
-                                let name =
-                                    format!("{}/{}", <$type as $crate::PluginManager>::name(), id);
+            use std::ffi::CStr;
 
-                                collectd_plugin_registration(name.as_str(), pl);
-                            }
-                        }
+            // Like the complex config, pairs are copied until the plugins are created on init
+            collectd_catch("config", || {
+                let key = CStr::from_ptr(key).to_str();
+                let value = CStr::from_ptr(value).to_str();
+                match (key, value) {
+                    (Ok(key), Ok(value)) => {
+                        $crate::stash_config_pair(
+                            <$type as $crate::PluginManager>::name(),
+                            key,
+                            value,
+                        );
+                        0
+                    }
+                    _ => {
+                        $crate::collectd_log(
+                            $crate::LogLevel::Error,
+                            &format!(
+                                "unable to decode config of {} as UTF-8",
+                                <$type as $crate::PluginManager>::name()
+                            ),
+                        );
+                        -1
                     }
-                    0
-                }
-                Err(ref e) => {
-                    collectd_log_err("collectd config", e);
-                    -1
                 }
-            }
+            })
+        }
+
+        // Creates the manager's plugins (along with the name that each is registered under) and
+        // the default read interval from the config, without registering anything with collectd
+        fn collectd_create_all_plugins(
+            config: Option<&[$crate::ConfigItem]>,
+        ) -> Result<(Option<$crate::export::Duration>, $crate::NamedPlugins), Error> {
+            let interval = config.map_or(Ok(None), $crate::config_read_interval)?;
+            let registration = <$type as $crate::PluginManager>::plugins(config)?;
+            let plugins =
+                $crate::name_plugins(<$type as $crate::PluginManager>::name(), registration)?;
+
+            Ok((interval, plugins))
         }
 
-        fn collectd_plugin_registration(name: &str, plugin: Box<$crate::Plugin>) {
+        fn collectd_plugin_registration(
+            name: &str,
+            plugin: Box<$crate::Plugin>,
+            default_interval: Option<$crate::export::Duration>,
+        ) {
             use std::ffi::CString;
             use std::os::raw::c_void;
             use std::ptr;
             use $crate::bindings::{
-                plugin_register_complex_read, plugin_register_flush, plugin_register_log,
-                plugin_register_write,
+                plugin_register_flush, plugin_register_log, plugin_register_missing,
+                plugin_register_notification, plugin_register_write,
             };
 
-            let pl: Box<Box<$crate::Plugin>> = Box::new(plugin);
+            let mut plugin = plugin;
 
-            // Grab all the properties we need until `into_raw` away
-            let should_read = pl.capabilities().has_read();
-            let should_log = pl.capabilities().has_log();
-            let should_write = pl.capabilities().has_write();
-            let should_flush = pl.capabilities().has_flush();
+            // Grab all the properties we need before the plugin is shared
+            let capabilities = plugin.capabilities();
+            let handle = $crate::RegistrationHandle::new(
+                <$type as $crate::PluginManager>::name(),
+                name,
+            );
+            plugin.registered(handle.clone());
+
+            let should_read = capabilities.has_read();
+            let should_log = capabilities.has_log();
+            let should_write = capabilities.has_write();
+            let should_flush = capabilities.has_flush();
+            let should_notify = capabilities.has_notification();
+            let should_shutdown = capabilities.has_shutdown();
+            let should_miss = capabilities.has_missing();
+            let should_init = capabilities.has_init();
+            let read_interval = plugin.read_interval().or(default_interval);
+            let read_group = plugin
+                .read_group()
+                .map(|x| CString::new(x).expect("Read group to not contain nulls"));
 
             let s = CString::new(name).expect("Plugin name to not contain nulls");
+            let entry = std::sync::Arc::new($crate::PluginEntry::new(plugin, handle));
+
+            // A plugin is initialized before any of its callbacks are registered, so a plugin that
+            // fails to initialize is dropped before collectd ever sees it, and doesn't take down
+            // its siblings (nor the manager) with it
+            if should_init {
+                let res = collectd_plugin_catch(&entry, "init", |plugin| {
+                    if let Err(ref e) = plugin.initialize() {
+                        collectd_log_err(&format!("{} init", name), e);
+                        -1
+                    } else {
+                        0
+                    }
+                });
+
+                if res != 0 || entry.is_disabled() {
+                    return;
+                }
+            }
+
+            if should_shutdown {
+                $crate::track_plugin(
+                    <$type as $crate::PluginManager>::name(),
+                    name,
+                    capabilities,
+                    std::sync::Arc::clone(&entry),
+                );
+            }
+
+            // The user data that is passed to read, writes, logs, etc. It is not passed to config
+            // or init. Collectd frees the user data of each callback separately, so each is given
+            // its own reference to the plugin.
+            let user_data = || {
+                let data = Box::new(std::sync::Arc::clone(&entry));
+                $crate::bindings::user_data_t {
+                    data: Box::into_raw(data) as *mut c_void,
+                    free_func: Some(collectd_plugin_free_user_data),
+                }
+            };
 
             // Plugin registration differs only a tiny bit between collectd-57 and older
             // versions. The one difference is that user_data_t went from mutable to not
@@ -370,38 +1774,100 @@ macro_rules! collectd_plugin {
             // encapsulated in a single crate instead of many others.
             #[cfg_attr(feature = "cargo-clippy", allow(unnecessary_mut_passed))]
             unsafe {
-                let plugin_ptr = Box::into_raw(pl) as *mut c_void;
-
-                // The user data that is passed to read, writes, logs, etc. It is not passed to
-                // config or init. Since user_data_t implements copy, we don't need to forget about
-                // it. See clippy suggestion (forget_copy)
-                let mut data = $crate::bindings::user_data_t {
-                    data: plugin_ptr,
-                    free_func: Some(collectd_plugin_free_user_data),
-                };
-
                 if should_read {
-                    plugin_register_complex_read(
-                        ptr::null(),
+                    let res = $crate::register_complex_read(
+                        read_group.as_ref().map_or(ptr::null(), |x| x.as_ptr()),
                         s.as_ptr(),
                         Some(collectd_plugin_read),
-                        $crate::get_default_interval(),
-                        &mut data,
+                        read_interval,
+                        &mut user_data(),
                     );
+
+                    if res == 0 {
+                        entry.handle.read_registered();
+                    }
                 }
 
                 if should_write {
-                    plugin_register_write(s.as_ptr(), Some(collectd_plugin_write), &mut data);
+                    plugin_register_write(
+                        s.as_ptr(),
+                        Some(collectd_plugin_write),
+                        &mut user_data(),
+                    );
                 }
 
                 if should_log {
-                    plugin_register_log(s.as_ptr(), Some(collectd_plugin_log), &mut data);
+                    plugin_register_log(s.as_ptr(), Some(collectd_plugin_log), &mut user_data());
                 }
 
                 if should_flush {
-                    plugin_register_flush(s.as_ptr(), Some(collectd_plugin_flush), &mut data);
+                    plugin_register_flush(
+                        s.as_ptr(),
+                        Some(collectd_plugin_flush),
+                        &mut user_data(),
+                    );
                 }
+
+                if should_miss {
+                    plugin_register_missing(
+                        s.as_ptr(),
+                        Some(collectd_plugin_missing),
+                        &mut user_data(),
+                    );
+                }
+
+                if should_notify {
+                    plugin_register_notification(
+                        s.as_ptr(),
+                        Some(collectd_plugin_notification),
+                        &mut user_data(),
+                    );
+                }
+            }
+        }
+
+        collectd_catch("module_register", || {
+            let s = CString::new(<$type as $crate::PluginManager>::name())
+                .expect("Plugin name to not contain nulls");
+
+            unsafe {
+                let keys = <$type as $crate::PluginManager>::config_keys();
+                if keys.is_empty() {
+                    plugin_register_complex_config(
+                        s.as_ptr(),
+                        Some(collectd_plugin_complex_config),
+                    );
+                } else {
+                    $crate::register_config(s.as_ptr(), keys, collectd_plugin_simple_config);
+                }
+
+                plugin_register_init(s.as_ptr(), Some(collectd_plugin_init));
+
+                plugin_register_shutdown(s.as_ptr(), Some(collectd_plugin_shutdown));
+            }
+
+            0
+        });
+    }};
+
+    ($($type:ty),+ $(,)*) => {
+        // This is the main entry point that collectd looks for. Each plugin manager will register
+        // callbacks for configuration related to its name. It also registers a callback for
+        // initialization, where plugins are registered once all configuration has been seen, and
+        // a callback for shutdown so plugins can clean up after themselves
+        #[no_mangle]
+        pub extern "C" fn module_register() {
+            use std::sync::atomic::{AtomicBool, Ordering};
+
+            // Collectd calls this once per `LoadPlugin` line, and all the lines that load this
+            // library (eg: through symlinks) share it. The name of the line being loaded isn't
+            // available, so every manager is registered on the first call
+            static REGISTERED: AtomicBool = AtomicBool::new(false);
+            if REGISTERED.swap(true, Ordering::SeqCst) {
+                return;
             }
+
+            $(collectd_plugin!(@manager $type);)+
         }
     };
 }
@@ -409,6 +1875,16 @@ macro_rules! collectd_plugin {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bindings::overrides::stub_registrations;
+
+    struct NoopPlugin;
+
+    impl Plugin for NoopPlugin {}
+
+    fn noop_entry(name: &str) -> Arc<PluginEntry> {
+        let handle = RegistrationHandle::new("noop", name);
+        Arc::new(PluginEntry::new(Box::new(NoopPlugin), handle))
+    }
 
     #[test]
     fn test_plugin_capabilities() {
@@ -420,4 +1896,473 @@ mod tests {
         assert_eq!(capabilities.has_read(), true);
         assert_eq!(capabilities.has_write(), false);
     }
+
+    #[test]
+    fn test_plugin_builder() {
+        use std::sync::atomic::AtomicUsize;
+
+        #[derive(Default)]
+        struct CountingPlugin {
+            reads: AtomicUsize,
+            flushes: AtomicUsize,
+            handle: Option<RegistrationHandle>,
+        }
+
+        impl ReadPlugin for CountingPlugin {
+            fn read_values(&self) -> Result<(), Error> {
+                self.reads.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        impl FlushPlugin for CountingPlugin {
+            fn flush(
+                &self,
+                _timeout: Option<Duration>,
+                _id: Option<Identifier>,
+            ) -> Result<(), Error> {
+                self.flushes.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        let plugin = PluginBuilder::new(CountingPlugin::default()).build();
+        assert_eq!(plugin.capabilities(), PluginCapabilities::default());
+        assert!(plugin.read_values().is_err());
+
+        let plugin = PluginBuilder::new(CountingPlugin::default())
+            .read()
+            .flush()
+            .build();
+        assert_eq!(
+            plugin.capabilities(),
+            PluginCapabilities::READ | PluginCapabilities::FLUSH
+        );
+        assert!(plugin.read_values().is_ok());
+        assert!(plugin.flush(None, None).is_ok());
+        assert!(plugin.log(LogLevel::Info, "hello").is_err());
+        assert_eq!(plugin.read_interval(), None);
+        assert_eq!(plugin.read_group(), None);
+        assert_eq!(plugin.panic_limit(), None);
+
+        let mut plugin = PluginBuilder::new(CountingPlugin::default())
+            .read()
+            .read_interval(Duration::seconds(30))
+            .read_group("counting")
+            .panic_limit(3)
+            .registered(|plugin, handle| plugin.handle = Some(handle))
+            .build();
+        assert_eq!(plugin.capabilities(), PluginCapabilities::READ);
+        assert_eq!(plugin.read_interval(), Some(Duration::seconds(30)));
+        assert_eq!(plugin.read_group(), Some("counting"));
+        assert_eq!(plugin.panic_limit(), Some(3));
+        plugin.registered(RegistrationHandle::new("counting", "counting"));
+    }
+
+    #[test]
+    fn test_plugin_builder_lifecycle() {
+        use std::sync::atomic::AtomicUsize;
+
+        #[derive(Default)]
+        struct ConnectionPlugin {
+            connections: AtomicUsize,
+        }
+
+        impl InitPlugin for ConnectionPlugin {
+            fn initialize(&self) -> Result<(), Error> {
+                self.connections.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        impl ShutdownPlugin for ConnectionPlugin {
+            fn shutdown(&self) -> Result<(), Error> {
+                self.connections.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        impl NotificationPlugin for ConnectionPlugin {
+            fn notification<'a>(&self, _notif: Notification<'a>) -> Result<(), Error> {
+                Ok(())
+            }
+        }
+
+        impl MissingPlugin for ConnectionPlugin {
+            fn missing_values<'a>(&self, _list: ValueList<'a>) -> Result<(), Error> {
+                Ok(())
+            }
+        }
+
+        let plugin = PluginBuilder::new(ConnectionPlugin::default())
+            .init()
+            .shutdown()
+            .build();
+        assert_eq!(
+            plugin.capabilities(),
+            PluginCapabilities::INIT | PluginCapabilities::SHUTDOWN
+        );
+        assert!(plugin.initialize().is_ok());
+        assert!(plugin.shutdown().is_ok());
+
+        let plugin = PluginBuilder::new(ConnectionPlugin::default())
+            .notification()
+            .missing()
+            .build();
+        assert_eq!(
+            plugin.capabilities(),
+            PluginCapabilities::NOTIFICATION | PluginCapabilities::MISSING
+        );
+        assert!(plugin.initialize().is_err());
+        assert!(plugin.shutdown().is_err());
+    }
+
+    #[test]
+    fn test_stateful_manager() {
+        struct Pool {
+            connections: AtomicUsize,
+        }
+
+        impl StatefulPluginManager for Pool {
+            fn name() -> &'static str {
+                "test_stateful_manager"
+            }
+
+            fn new(_config: Option<&[ConfigItem]>) -> Result<Self, Error> {
+                Ok(Pool {
+                    connections: AtomicUsize::new(0),
+                })
+            }
+
+            fn plugins(manager: &Arc<Self>) -> Result<PluginRegistration, Error> {
+                manager.connections.fetch_add(1, Ordering::SeqCst);
+                Ok(PluginRegistration::Multiple(vec![]))
+            }
+
+            fn initialize(&self) -> Result<(), Error> {
+                self.connections.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+
+            fn shutdown(&self) -> Result<(), Error> {
+                self.connections.fetch_sub(2, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        type Manager = StatefulManager<Pool>;
+        assert!(Manager::instance().is_none());
+        assert!(<Manager as PluginManager>::initialize().is_err());
+
+        assert!(<Manager as PluginManager>::plugins(None).is_ok());
+        assert!(<Manager as PluginManager>::initialize().is_ok());
+        let pool = Manager::instance().unwrap();
+        assert_eq!(pool.connections.load(Ordering::SeqCst), 2);
+
+        assert!(<Manager as PluginManager>::shutdown().is_ok());
+        assert_eq!(pool.connections.load(Ordering::SeqCst), 0);
+        assert!(Manager::instance().is_none());
+        assert!(<Manager as PluginManager>::shutdown().is_ok());
+    }
+
+    #[test]
+    fn test_config_read_interval() {
+        let interval = |values| {
+            config_read_interval(&[ConfigItem {
+                key: "Interval",
+                values,
+                children: vec![],
+            }])
+        };
+
+        assert_eq!(config_read_interval(&[]).unwrap(), None);
+        assert_eq!(
+            interval(vec![ConfigValue::Number(300.0)]).unwrap(),
+            Some(Duration::seconds(300))
+        );
+        assert_eq!(
+            interval(vec![ConfigValue::Number(0.5)]).unwrap(),
+            Some(Duration::milliseconds(500))
+        );
+        assert!(interval(vec![ConfigValue::Number(-1.0)]).is_err());
+        assert!(interval(vec![ConfigValue::String("10")]).is_err());
+        assert!(interval(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_merge_config() {
+        let block = |children: Vec<ConfigItem>| {
+            OwnedConfigItem::from(&ConfigItem {
+                key: "Plugin",
+                values: vec![ConfigValue::String("myplugin")],
+                children,
+            })
+        };
+
+        let node = |name| ConfigItem {
+            key: "Node",
+            values: vec![ConfigValue::String(name)],
+            children: vec![ConfigItem {
+                key: "Port",
+                values: vec![ConfigValue::Number(2003.0)],
+                children: vec![],
+            }],
+        };
+
+        let option = |key| ConfigItem {
+            key,
+            values: vec![ConfigValue::Boolean(true)],
+            children: vec![],
+        };
+
+        let interval = |secs| ConfigItem {
+            key: "Interval",
+            values: vec![ConfigValue::Number(secs)],
+            children: vec![],
+        };
+
+        let blocks = vec![
+            block(vec![interval(10.0), option("StoreRates"), node("a")]),
+            block(vec![interval(10.0), node("b"), option("StoreRates")]),
+        ];
+        let merged = merge_config(&blocks).unwrap();
+        assert_eq!(
+            merged,
+            vec![
+                interval(10.0),
+                option("StoreRates"),
+                node("a"),
+                node("b"),
+                option("StoreRates"),
+            ]
+        );
+
+        let blocks = vec![
+            block(vec![interval(10.0)]),
+            block(vec![node("b")]),
+            block(vec![interval(300.0)]),
+        ];
+        let err = merge_config(&blocks).unwrap_err();
+        assert_eq!(
+            format!("{}", err),
+            "conflicting config: `Interval` is set to different values in block 1 and block 3"
+        );
+    }
+
+    #[test]
+    fn test_name_plugins() {
+        let names = |registration| {
+            name_plugins("myplugin", registration)
+                .map(|plugins| plugins.into_iter().map(|x| x.0).collect::<Vec<String>>())
+        };
+
+        let single = PluginRegistration::Single(Box::new(NoopPlugin));
+        assert_eq!(names(single).unwrap(), vec!["myplugin"]);
+
+        let multiple = PluginRegistration::Multiple(vec![
+            (String::from("a"), Box::new(NoopPlugin)),
+            (String::from("b"), Box::new(NoopPlugin)),
+        ]);
+        assert_eq!(names(multiple).unwrap(), vec!["myplugin/a", "myplugin/b"]);
+
+        let duplicate = PluginRegistration::Multiple(vec![
+            (String::from("a"), Box::new(NoopPlugin)),
+            (String::from("a"), Box::new(NoopPlugin)),
+        ]);
+        assert_eq!(
+            format!("{}", names(duplicate).unwrap_err()),
+            "more than one plugin is registered as myplugin/a"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_typed_plugin_manager() {
+        #[derive(Deserialize, Default)]
+        #[serde(rename_all = "PascalCase")]
+        #[serde(deny_unknown_fields)]
+        struct TypedConfig {
+            fail: bool,
+        }
+
+        struct TypedManager;
+
+        impl TypedPluginManager for TypedManager {
+            type Config = TypedConfig;
+
+            fn name() -> &'static str {
+                "typed"
+            }
+
+            fn config_keys() -> &'static [&'static str] {
+                &["Fail"]
+            }
+
+            fn plugins_typed(config: TypedConfig) -> Result<PluginRegistration, Error> {
+                if config.fail {
+                    Err(format_err!("asked to fail"))
+                } else {
+                    Ok(PluginRegistration::Multiple(vec![]))
+                }
+            }
+        }
+
+        let item = |key, values| ConfigItem {
+            key,
+            values,
+            children: vec![],
+        };
+
+        assert_eq!(<TypedManager as PluginManager>::config_keys(), &["Fail"]);
+        assert!(<TypedManager as PluginManager>::plugins(None).is_ok());
+
+        let config = vec![item("Fail", vec![ConfigValue::Boolean(false)])];
+        assert!(<TypedManager as PluginManager>::plugins(Some(&config)).is_ok());
+
+        let config = vec![item("Fail", vec![ConfigValue::Boolean(true)])];
+        assert!(<TypedManager as PluginManager>::plugins(Some(&config)).is_err());
+
+        let config = vec![item("Typo", vec![ConfigValue::Boolean(true)])];
+        let err = <TypedManager as PluginManager>::plugins(Some(&config))
+            .err()
+            .unwrap();
+        assert_eq!(format!("{}", err), "unable to deserialize config of typed");
+    }
+
+    #[test]
+    fn test_take_config_pairs() {
+        stash_config_pair("pairs_a", "Host", "localhost");
+        stash_config_pair("pairs_b", "Port", "8080");
+        stash_config_pair("pairs_a", "Host", "example.com");
+
+        let block = take_config_pairs("pairs_a").unwrap();
+        let host = |value| ConfigItem {
+            key: "Host",
+            values: vec![ConfigValue::String(value)],
+            children: vec![],
+        };
+
+        assert_eq!(
+            block.as_config_item(),
+            ConfigItem {
+                key: "Plugin",
+                values: vec![ConfigValue::String("pairs_a")],
+                children: vec![host("localhost"), host("example.com")],
+            }
+        );
+        assert!(take_config_pairs("pairs_a").is_none());
+        assert!(take_config_pairs("pairs_b").is_some());
+    }
+
+    #[test]
+    fn test_stash_config() {
+        let block = |name| ConfigItem {
+            key: "Plugin",
+            values: vec![ConfigValue::String(name)],
+            children: vec![],
+        };
+
+        stash_config("stash_a", &block("a1"));
+        stash_config("stash_b", &block("b1"));
+        stash_config("stash_a", &block("a2"));
+
+        let blocks = take_config("stash_a").unwrap();
+        let blocks: Vec<ConfigItem> = blocks.iter().map(|x| x.as_config_item()).collect();
+        assert_eq!(blocks, vec![block("a1"), block("a2")]);
+        assert!(take_config("stash_a").is_none());
+        assert_eq!(take_config("stash_b").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_panic_message() {
+        use std::panic;
+
+        let res = panic::catch_unwind(|| panic!("static message"));
+        assert_eq!(panic_message(&*res.unwrap_err()), "static message");
+
+        let res = panic::catch_unwind(|| panic!("formatted {}", 10));
+        assert_eq!(panic_message(&*res.unwrap_err()), "formatted 10");
+    }
+
+    #[test]
+    fn test_plugin_entry_panic_limit() {
+        struct FragilePlugin;
+
+        impl Plugin for FragilePlugin {
+            fn panic_limit(&self) -> Option<usize> {
+                Some(2)
+            }
+        }
+
+        let handle = RegistrationHandle::new("fragile", "fragile");
+        let entry = PluginEntry::new(Box::new(FragilePlugin), handle);
+        assert!(!entry.is_disabled());
+        assert_eq!(entry.record_panic(), None);
+        assert!(!entry.is_disabled());
+        assert_eq!(entry.record_panic(), Some(2));
+        assert!(entry.is_disabled());
+        assert_eq!(entry.record_panic(), None);
+    }
+
+    #[test]
+    fn test_untrack_plugins() {
+        let caps = PluginCapabilities::SHUTDOWN;
+        track_plugin("untrack_a", "untrack_a/1", caps, noop_entry("untrack_a/1"));
+        track_plugin("untrack_b", "untrack_b", caps, noop_entry("untrack_b"));
+        track_plugin("untrack_a", "untrack_a/2", caps, noop_entry("untrack_a/2"));
+
+        let names: Vec<String> = untrack_plugins("untrack_a")
+            .into_iter()
+            .map(|x| x.name)
+            .collect();
+        assert_eq!(names, vec!["untrack_a/1", "untrack_a/2"]);
+        assert_eq!(untrack_plugins("untrack_a").len(), 0);
+        assert_eq!(untrack_plugins("untrack_b").len(), 1);
+    }
+
+    #[test]
+    fn test_registration_handle_unregister() {
+        let caps = PluginCapabilities::READ | PluginCapabilities::SHUTDOWN;
+        track_plugin("handle_a", "handle_a", caps, noop_entry("handle_a"));
+
+        let handle = RegistrationHandle::new("handle_a", "handle_a");
+        let other = handle.clone();
+        assert_eq!(handle.name(), "handle_a");
+        assert!(!other.is_unregistered());
+
+        handle.read_registered();
+        handle.unregister();
+        assert!(other.is_unregistered());
+        assert_eq!(tracked_plugins("handle_a").len(), 0);
+
+        // Unregistering again is a no-op, and the read callback is only removed once
+        other.unregister();
+        other.unregister_read();
+        assert!(handle.is_unregistered());
+
+        let removals = stub_registrations()
+            .into_iter()
+            .filter(|x| x.0 == "unregister read" && x.1 == "handle_a")
+            .count();
+        assert_eq!(removals, 1);
+    }
+
+    #[test]
+    fn test_untrack_plugin() {
+        let caps = PluginCapabilities::INIT | PluginCapabilities::READ;
+        track_plugin("untrack_c", "untrack_c/1", caps, noop_entry("untrack_c/1"));
+        track_plugin("untrack_c", "untrack_c/2", caps, noop_entry("untrack_c/2"));
+
+        let removed = untrack_plugin("untrack_c", "untrack_c/1").unwrap();
+        assert_eq!(removed.name, "untrack_c/1");
+        assert_eq!(removed.capabilities, caps);
+        assert!(untrack_plugin("untrack_c", "untrack_c/1").is_none());
+
+        let names: Vec<String> = tracked_plugins("untrack_c")
+            .into_iter()
+            .map(|x| x.name)
+            .collect();
+        assert_eq!(names, vec!["untrack_c/2"]);
+        assert_eq!(untrack_plugins("untrack_c").len(), 1);
+    }
 }